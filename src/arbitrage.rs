@@ -0,0 +1,315 @@
+//! Arbitrage
+//!
+//! Buys on one merchant and sells on another one when the best ask of the first is below
+//! the best bid of the second. Both legs are market orders sent at the same time.
+use crate::bookkeeper::{Bookkeeper, Strategy};
+use crate::calculators::{AmountCalculator, FeeModel, Fees, ProfitCalculator};
+use crate::decimal::{self, Decimal};
+use crate::error::Error;
+use crate::fan_out::FanOut;
+use crate::instrument::Instruments;
+use crate::risk::RiskManager;
+use crate::limit_master::OrderEntity;
+use agnostic::merchant::Merchant;
+use agnostic::order::Order;
+use agnostic::trade::Trade;
+use agnostic::trading_pair::{Coins, Side, Target, TradingPair};
+
+/// The best orders and balances of a single merchant.
+struct Quote {
+    ask: Order,
+    bid: Order,
+    /// Quote coin converted to the base coin at the ask price.
+    buy_balance: Decimal,
+    /// Base coin.
+    sell_balance: Decimal,
+}
+
+/// Crossed books of two merchants and the amounts to trade on them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Opportunity {
+    pub buy: OrderEntity<Order>,
+    pub sell: OrderEntity<Order>,
+    /// Profit net of fees.
+    pub profit: Decimal,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Legs {
+    pub buy: OrderEntity<Trade>,
+    pub sell: OrderEntity<Trade>,
+}
+
+pub struct Arbitrage<'a> {
+    merchants: Vec<&'a dyn Merchant>,
+    coins: Coins,
+    amount_calculator: AmountCalculator,
+    min_profit: Decimal,
+    fan_out: FanOut,
+    fee_model: FeeModel,
+    instruments: Instruments,
+    risk_manager: RiskManager,
+}
+
+impl<'a> Arbitrage<'a> {
+    /// Until a fee model is set, the fee of `amount_calculator` is paid by both legs.
+    pub fn new(
+        merchants: Vec<&'a dyn Merchant>,
+        coins: Coins,
+        amount_calculator: AmountCalculator,
+        min_profit: Decimal,
+    ) -> Arbitrage<'a> {
+        Arbitrage {
+            merchants,
+            coins,
+            amount_calculator,
+            min_profit,
+            fan_out: FanOut::default(),
+            fee_model: FeeModel::new(Fees::flat(amount_calculator.fee)),
+            instruments: Instruments::default(),
+            risk_manager: RiskManager::default(),
+        }
+    }
+
+    pub fn with_fan_out(mut self, fan_out: FanOut) -> Self {
+        self.fan_out = fan_out;
+        self
+    }
+
+    pub fn with_fee_model(mut self, fee_model: FeeModel) -> Self {
+        self.fee_model = fee_model;
+        self
+    }
+
+    pub fn with_instruments(mut self, instruments: Instruments) -> Self {
+        self.instruments = instruments;
+        self
+    }
+
+    /// Both legs are priced at the best orders, so only the limits without a price band
+    /// apply to them.
+    pub fn with_risk_manager(mut self, risk_manager: RiskManager) -> Self {
+        self.risk_manager = risk_manager;
+        self
+    }
+
+    /// Fires both legs of the most profitable opportunity and commits the filled ones to
+    /// the `bookkeeper`. The first failure, either of a leg or of its commit, is reported
+    /// after both legs have been committed.
+    pub async fn iterate(&self, bookkeeper: &mut Bookkeeper) -> Result<Option<Legs>, Error> {
+        let opportunity = match self.find_opportunity().await {
+            Some(opportunity) => opportunity,
+            None => return Ok(None),
+        };
+        log::info!(
+            "Arbitrage: Buy on {} at {:.5} Sell on {} at {:.5} Profit {:.5}",
+            opportunity.buy.merchant_id,
+            opportunity.buy.order.price,
+            opportunity.sell.merchant_id,
+            opportunity.sell.order.price,
+            opportunity.profit);
+        // Neither leg is sent if one of them breaks the precision rules or the limits.
+        let legs = self.normalize(opportunity.buy, opportunity.sell)?;
+        self.risk_manager.check_all(&[
+            (legs[0].merchant_id, &legs[0].order, None),
+            (legs[1].merchant_id, &legs[1].order, None),
+        ])?;
+        let results = self
+            .fan_out
+            .join(legs.iter().map(|leg| {
+                let merchant = self.merchant(leg.merchant_id);
+                merchant.trader().create_order(leg.order.clone())
+            }))
+            .await;
+        let mut trades = Vec::with_capacity(legs.len());
+        let mut failure = None;
+        for (leg, result) in legs.iter().zip(results.into_iter()) {
+            match result {
+                Ok(trade) => {
+                    let committed = bookkeeper.commit_trade(
+                        trade.clone(),
+                        leg.merchant_id,
+                        Strategy::Arbitrage,
+                    );
+                    if let Err(error) = committed {
+                        log::error!("{}", error);
+                        failure = failure.or(Some(error));
+                    }
+                    trades.push(OrderEntity::new(leg.merchant_id, trade));
+                }
+                Err(error) => {
+                    let error = Error::trader(
+                        leg.merchant_id,
+                        leg.order.trading_pair.clone(),
+                        format!("Failed to create an order {:#?}: {}", leg.order, error),
+                    );
+                    log::error!("{}", error);
+                    failure = failure.or(Some(error));
+                }
+            }
+        }
+        if let Some(error) = failure {
+            return Err(error);
+        }
+        let sell = trades.pop().expect("Sell leg");
+        let buy = trades.pop().expect("Buy leg");
+        Ok(Some(Legs { buy, sell }))
+    }
+
+    /// The best pair of crossed books. Merchants which fail to respond are skipped.
+    pub async fn find_opportunity(&self) -> Option<Opportunity> {
+        let quotes = self
+            .fan_out
+            .join(self.merchants.iter().map(|merchant| self.quote(*merchant)))
+            .await;
+        let quotes: Vec<(&'a dyn Merchant, Quote)> = self
+            .merchants
+            .iter()
+            .zip(quotes.into_iter())
+            .filter_map(|(merchant, quote)| match quote {
+                Ok(quote) => Some((*merchant, quote)),
+                Err(error) => {
+                    log::warn!("{}", error);
+                    None
+                }
+            })
+            .collect();
+        let mut result: Option<Opportunity> = None;
+        for (buy_merchant, buy_quote) in quotes.iter() {
+            for (sell_merchant, sell_quote) in quotes.iter() {
+                if buy_merchant.id() == sell_merchant.id() {
+                    continue;
+                }
+                let buy_fee = self.fee_model.fee(buy_merchant.id(), Target::Market);
+                let sell_fee = self.fee_model.fee(sell_merchant.id(), Target::Market);
+                let profit_calculator = ProfitCalculator::new(sell_fee, buy_fee);
+                let profit = match profit_calculator
+                    .evaluate(
+                        decimal::from_f64(sell_quote.bid.price),
+                        decimal::from_f64(buy_quote.ask.price),
+                    )
+                {
+                    Some(profit) if profit >= self.min_profit => profit,
+                    _ => continue,
+                };
+                if let Some(the_best) = &result {
+                    if the_best.profit >= profit {
+                        continue;
+                    }
+                }
+                // Both legs trade the smaller of the amounts once they are normalized.
+                let amount_calculator =
+                    self.amount_calculator.with_merchant_fee(buy_fee.max(sell_fee));
+                let (buy_amount, sell_amount) = match amount_calculator.calculate(
+                    &buy_quote.ask,
+                    buy_quote.buy_balance,
+                    &sell_quote.bid,
+                    sell_quote.sell_balance,
+                ) {
+                    Some(amounts) => amounts,
+                    None => continue,
+                };
+                result = Some(Opportunity {
+                    buy: OrderEntity::new(buy_merchant.id(), Order {
+                        amount: decimal::to_f64(buy_amount),
+                        ..buy_quote.ask.clone()
+                    }),
+                    sell: OrderEntity::new(sell_merchant.id(), Order {
+                        amount: decimal::to_f64(sell_amount),
+                        ..sell_quote.bid.clone()
+                    }),
+                    profit,
+                });
+            }
+        }
+        result
+    }
+
+    async fn quote(&self, merchant: &dyn Merchant) -> Result<Quote, Error> {
+        let buy_pair = self.trading_pair(Side::Buy);
+        let sell_pair = self.trading_pair(Side::Sell);
+        let sniffer = merchant.sniffer();
+        let accountant = merchant.accountant();
+        let (asks, bids) = futures::future::join(
+            self.fan_out.call(sniffer.all_the_best_orders(buy_pair.clone(), 1)),
+            self.fan_out.call(sniffer.all_the_best_orders(sell_pair.clone(), 1)),
+        )
+        .await;
+        let (quote_currency, base_currency) = futures::future::join(
+            self.fan_out.call(accountant.ask(buy_pair.coin_to_spend())),
+            self.fan_out.call(accountant.ask(sell_pair.coin_to_spend())),
+        )
+        .await;
+        let ask = best_order(merchant, &buy_pair, asks)?;
+        let bid = best_order(merchant, &sell_pair, bids)?;
+        let quote_currency = quote_currency
+            .map_err(|error| Error::accountant(merchant.id(), buy_pair.clone(), error))?;
+        let base_currency = base_currency
+            .map_err(|error| Error::accountant(merchant.id(), sell_pair.clone(), error))?;
+        let buy_balance = agnostic::price::convert_to_base_coin_amount(
+            buy_pair.target.clone(),
+            buy_pair.side.clone(),
+            &ask.price.into(),
+            quote_currency.amount,
+        );
+        Ok(Quote {
+            ask,
+            bid,
+            buy_balance: decimal::from_f64(buy_balance),
+            sell_balance: decimal::from_f64(base_currency.amount),
+        })
+    }
+
+    /// Both legs trade the smaller amount, rounded by the rules of both merchants.
+    fn normalize(
+        &self,
+        buy: OrderEntity<Order>,
+        sell: OrderEntity<Order>,
+    ) -> Result<[OrderEntity<Order>; 2], Error> {
+        let mut buy_order = self.instruments.normalize(buy.merchant_id, buy.order)?;
+        let mut sell_order = self.instruments.normalize(sell.merchant_id, sell.order)?;
+        // The amounts are only rounded down, so the larger one shrinks until both lot
+        // steps agree on it.
+        while buy_order.amount != sell_order.amount {
+            let amount = buy_order.amount.min(sell_order.amount);
+            buy_order = self
+                .instruments
+                .normalize(buy.merchant_id, Order { amount, ..buy_order })?;
+            sell_order = self
+                .instruments
+                .normalize(sell.merchant_id, Order { amount, ..sell_order })?;
+        }
+        Ok([
+            OrderEntity::new(buy.merchant_id, buy_order),
+            OrderEntity::new(sell.merchant_id, sell_order),
+        ])
+    }
+
+    fn trading_pair(&self, side: Side) -> TradingPair {
+        TradingPair {
+            coins: self.coins.clone(),
+            target: Target::Market,
+            side,
+        }
+    }
+
+    fn merchant(&self, id: &str) -> &'a dyn Merchant {
+        *self
+            .merchants
+            .iter()
+            .find(|merchant| merchant.id() == id)
+            .expect("Opportunity of an unknown merchant")
+    }
+}
+
+fn best_order(
+    merchant: &dyn Merchant,
+    pair: &TradingPair,
+    orders: Result<Vec<Order>, String>,
+) -> Result<Order, Error> {
+    orders
+        .map_err(|error| Error::sniffer(merchant.id(), pair.clone(), error))?
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::sniffer(merchant.id(), pair.clone(), "Empty stock".to_owned()))
+}
@@ -0,0 +1,22 @@
+//! Files which are replaced as a whole. A crash leaves either the old or the new content,
+//! never a truncated one.
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const TEMPORARY_SUFFIX: &str = ".tmp";
+
+/// `path` with `suffix` appended to its file name.
+pub(crate) fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.to_path_buf().into_os_string();
+    path.push(suffix);
+    path.into()
+}
+
+/// Writes `bytes` to a temporary sibling of `path` and renames it over `path`.
+pub(crate) fn write(path: &Path, bytes: &[u8]) -> Result<(), std::io::Error> {
+    let temporary_path = sibling(path, TEMPORARY_SUFFIX);
+    let mut temporary = std::fs::File::create(&temporary_path)?;
+    temporary.write_all(bytes)?;
+    temporary.sync_all()?;
+    std::fs::rename(&temporary_path, path)
+}
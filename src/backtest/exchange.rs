@@ -0,0 +1,572 @@
+//! Matching engine of a single pair. Market orders take the liquidity of the book at
+//! once, limit orders rest until a later book crosses their price. Both consume the
+//! levels they are matched with, so the same liquidity is not taken twice before the next
+//! book.
+use crate::backtest::replay::{BookSnapshot, Level};
+use crate::bookkeeper;
+use crate::calculators::Fees;
+use crate::decimal::{self, Decimal};
+use agnostic::currency::Currency;
+use agnostic::market::{Accountant, Future, Trader};
+use agnostic::order::{Order, OrderWithId};
+use agnostic::trade::{Trade, TradeResult};
+use agnostic::trading_pair::{Coin, Coins, Side, Target, TradingPair};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Limit order waiting for the book to cross its price.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RestingOrder {
+    pub id: String,
+    pub side: Side,
+    pub price: Decimal,
+    /// The rest of the amount which is not filled yet.
+    pub amount: Decimal,
+}
+
+/// A match of my order, market or limit one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fill {
+    pub order_id: String,
+    pub side: Side,
+    pub target: Target,
+    pub price: Decimal,
+    pub amount: Decimal,
+}
+
+/// The side of `Exchange::book` which answers the sniffer: market orders take the
+/// opposite side of the book, limit orders join it.
+pub fn book_side(trading_pair: &TradingPair) -> Side {
+    match trading_pair.target {
+        Target::Market => trading_pair.side,
+        Target::Limit => trading_pair.reversed_side().side,
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Exchange {
+    pub coins: Coins,
+    /// Market orders pay the taker fee, filled limit orders the maker one. The fee is
+    /// paid in the quote coin.
+    pub fees: Fees,
+    time: i64,
+    bids: Vec<Level>,
+    asks: Vec<Level>,
+    /// Free balances, the locked ones belong to the resting orders.
+    base: Decimal,
+    quote: Decimal,
+    orders: Vec<RestingOrder>,
+    fills: Vec<Fill>,
+    next_id: u64,
+}
+
+impl Exchange {
+    pub fn new(coins: Coins, base: Decimal, quote: Decimal) -> Exchange {
+        Exchange {
+            coins,
+            fees: Fees::default(),
+            time: 0,
+            bids: Vec::new(),
+            asks: Vec::new(),
+            base,
+            quote,
+            orders: Vec::new(),
+            fills: Vec::new(),
+            next_id: 1,
+        }
+    }
+
+    pub fn with_fees(mut self, fees: Fees) -> Self {
+        self.fees = fees;
+        self
+    }
+
+    pub fn time(&self) -> i64 {
+        self.time
+    }
+
+    /// Asks for `Side::Buy` and bids for `Side::Sell`, as my market order takes them.
+    pub fn book(&self, side: Side) -> &[Level] {
+        match side {
+            Side::Buy => &self.asks,
+            Side::Sell => &self.bids,
+        }
+    }
+
+    pub fn mid_price(&self) -> Option<Decimal> {
+        match (self.bids.first(), self.asks.first()) {
+            (Some(bid), Some(ask)) => Some((bid.price + ask.price) / Decimal::from(2)),
+            _ => None,
+        }
+    }
+
+    /// Free and locked base coin.
+    pub fn base(&self) -> (Decimal, Decimal) {
+        let locked = self
+            .orders
+            .iter()
+            .filter(|order| order.side == Side::Sell)
+            .map(|order| order.amount)
+            .sum();
+        (self.base, locked)
+    }
+
+    /// Free and locked quote coin.
+    pub fn quote(&self) -> (Decimal, Decimal) {
+        let locked = self
+            .orders
+            .iter()
+            .filter(|order| order.side == Side::Buy)
+            .map(|order| self.buy_cost(order.price, order.amount))
+            .sum();
+        (self.quote, locked)
+    }
+
+    pub fn orders(&self) -> &[RestingOrder] {
+        &self.orders
+    }
+
+    /// Every fill since the start.
+    pub fn fills(&self) -> &[Fill] {
+        &self.fills
+    }
+
+    /// My resting orders of both sides as the sniffer reports them, they are told apart
+    /// by the trading pair.
+    pub fn my_orders(
+        &self,
+        trading_pair: &TradingPair,
+    ) -> Result<Vec<OrderWithId>, String> {
+        if trading_pair.coins != self.coins {
+            return Err(format!("{:?} is not traded", trading_pair.coins));
+        }
+        let orders = self
+            .orders
+            .iter()
+            .map(|order| OrderWithId {
+                id: order.id.clone(),
+                trading_pair: TradingPair {
+                    coins: self.coins,
+                    side: order.side,
+                    target: Target::Limit,
+                },
+                price: decimal::to_f64(order.price),
+                amount: decimal::to_f64(order.amount),
+            })
+            .collect();
+        Ok(orders)
+    }
+
+    /// Free and locked amounts of `coin` as the accountant reports them.
+    pub fn currency(&self, coin: Coin) -> Result<Currency, String> {
+        let base = TradingPair {
+            coins: self.coins,
+            side: Side::Sell,
+            target: Target::Market,
+        };
+        let (amount, held) = if coin == base.coin_to_spend() {
+            self.base()
+        } else if coin == base.reversed_side().coin_to_spend() {
+            self.quote()
+        } else {
+            return Err(format!("{:?} is not traded", coin));
+        };
+        Ok(Currency {
+            coin,
+            amount: decimal::to_f64(amount),
+            held: decimal::to_f64(held),
+        })
+    }
+
+    /// Places `order` as the trader does. A market order is filled at once, a limit order
+    /// rests even if it crosses the book.
+    pub fn execute(&mut self, order: &Order) -> Result<Trade, String> {
+        if order.trading_pair.coins != self.coins {
+            return Err(format!(
+                "{:?} is not traded, only {:?}",
+                order.trading_pair.coins, self.coins
+            ));
+        }
+        let side = order.trading_pair.side;
+        let amount = decimal::from_f64(order.amount);
+        match order.trading_pair.target {
+            Target::Market => self.market(side, amount).map(|fill| {
+                Trade::Market(TradeResult {
+                    id: fill.order_id,
+                    trading_pair: order.trading_pair.clone(),
+                    price: decimal::to_f64(fill.price),
+                    amount: decimal::to_f64(fill.amount),
+                })
+            }),
+            Target::Limit => {
+                let price = decimal::from_f64(order.price);
+                self.limit(side, price, amount).map(|id| {
+                    Trade::Limit(OrderWithId {
+                        id,
+                        trading_pair: order.trading_pair.clone(),
+                        price: order.price,
+                        amount: order.amount,
+                    })
+                })
+            }
+        }
+    }
+
+    /// Replaces the book and fills my resting orders it crosses. Snapshots of other coins
+    /// are ignored, so are the levels without a positive price and amount.
+    pub fn update_book(&mut self, snapshot: &BookSnapshot) {
+        if snapshot.coins != bookkeeper::Coins::from(self.coins.clone()) {
+            return;
+        }
+        self.time = snapshot.time;
+        self.bids = valid_levels(snapshot.bids.clone());
+        self.asks = valid_levels(snapshot.asks.clone());
+        self.match_resting_orders();
+    }
+
+    /// Replaces a single side of the book, `side` is the one of `Exchange::book`.
+    pub fn update_book_side(&mut self, side: Side, levels: Vec<Level>, time: i64) {
+        self.time = time;
+        let mut levels = valid_levels(levels);
+        match side {
+            Side::Buy => {
+                levels.sort_by(|left, right| left.price.cmp(&right.price));
+                self.asks = levels;
+            }
+            Side::Sell => {
+                levels.sort_by(|left, right| right.price.cmp(&left.price));
+                self.bids = levels;
+            }
+        }
+        self.match_resting_orders();
+    }
+
+    /// The fill is at the average price. The order is cut to the depth of the book and to
+    /// the balance.
+    pub fn market(&mut self, side: Side, amount: Decimal) -> Result<Fill, String> {
+        let taker = self.fees.taker;
+        let (mut filled, mut notional) = (Decimal::ZERO, Decimal::ZERO);
+        let (base, quote) = (self.base, self.quote);
+        let levels = match side {
+            Side::Buy => &mut self.asks,
+            Side::Sell => &mut self.bids,
+        };
+        for level in levels.iter_mut() {
+            let mut take = level.amount.min(amount - filled);
+            take = match side {
+                Side::Buy => {
+                    let spent = notional * (Decimal::ONE + taker);
+                    take.min((quote - spent) / (level.price * (Decimal::ONE + taker)))
+                }
+                Side::Sell => take.min(base - filled),
+            };
+            if take <= Decimal::ZERO {
+                break;
+            }
+            level.amount -= take;
+            filled += take;
+            notional += take * level.price;
+        }
+        levels.retain(|level| level.amount > Decimal::ZERO);
+        if filled.is_zero() {
+            return Err(format!("Market {:?} of {} is not filled", side, amount));
+        }
+        match side {
+            Side::Buy => {
+                self.quote -= notional * (Decimal::ONE + taker);
+                self.base += filled;
+            }
+            Side::Sell => {
+                self.base -= filled;
+                self.quote += notional * (Decimal::ONE - taker);
+            }
+        }
+        let fill = Fill {
+            order_id: self.next_id(),
+            side,
+            target: Target::Market,
+            price: notional / filled,
+            amount: filled,
+        };
+        self.fills.push(fill.clone());
+        Ok(fill)
+    }
+
+    /// Locks the balance of the order and returns its id. The order is matched with the
+    /// next books only.
+    pub fn limit(
+        &mut self,
+        side: Side,
+        price: Decimal,
+        amount: Decimal,
+    ) -> Result<String, String> {
+        if price <= Decimal::ZERO || amount <= Decimal::ZERO {
+            return Err(format!("Invalid limit order {} at {}", amount, price));
+        }
+        match side {
+            Side::Buy => {
+                let cost = self.buy_cost(price, amount);
+                if cost > self.quote {
+                    return Err(format!(
+                        "Insufficient quote balance {} for {}",
+                        self.quote, cost
+                    ));
+                }
+                self.quote -= cost;
+            }
+            Side::Sell => {
+                if amount > self.base {
+                    return Err(format!(
+                        "Insufficient base balance {} for {}",
+                        self.base, amount
+                    ));
+                }
+                self.base -= amount;
+            }
+        }
+        let id = self.next_id();
+        self.orders.push(RestingOrder {
+            id: id.clone(),
+            side,
+            price,
+            amount,
+        });
+        Ok(id)
+    }
+
+    /// Releases the balance locked by the rest of the order.
+    pub fn cancel(&mut self, id: &str) -> Result<(), String> {
+        let position = self
+            .orders
+            .iter()
+            .position(|order| order.id == id)
+            .ok_or_else(|| format!("Unknown order {}", id))?;
+        let order = self.orders.remove(position);
+        match order.side {
+            Side::Buy => self.quote += self.buy_cost(order.price, order.amount),
+            Side::Sell => self.base += order.amount,
+        }
+        Ok(())
+    }
+
+    fn match_resting_orders(&mut self) {
+        let maker = self.fees.maker;
+        let mut fills = Vec::new();
+        for order in self.orders.iter_mut() {
+            let levels = match order.side {
+                Side::Buy => &mut self.asks,
+                Side::Sell => &mut self.bids,
+            };
+            for level in levels.iter_mut() {
+                let crosses = match order.side {
+                    Side::Buy => level.price <= order.price,
+                    Side::Sell => level.price >= order.price,
+                };
+                if !crosses || order.amount.is_zero() {
+                    break;
+                }
+                let take = level.amount.min(order.amount);
+                level.amount -= take;
+                order.amount -= take;
+                fills.push(Fill {
+                    order_id: order.id.clone(),
+                    side: order.side,
+                    target: Target::Limit,
+                    price: order.price,
+                    amount: take,
+                });
+            }
+            levels.retain(|level| level.amount > Decimal::ZERO);
+        }
+        self.orders.retain(|order| order.amount > Decimal::ZERO);
+        for fill in fills.into_iter() {
+            // The quote of the buy orders is locked together with the fee.
+            match fill.side {
+                Side::Buy => self.base += fill.amount,
+                Side::Sell => {
+                    self.quote += fill.price * fill.amount * (Decimal::ONE - maker)
+                }
+            }
+            self.fills.push(fill);
+        }
+    }
+
+    fn buy_cost(&self, price: Decimal, amount: Decimal) -> Decimal {
+        price * amount * (Decimal::ONE + self.fees.maker)
+    }
+
+    fn next_id(&mut self) -> String {
+        let id = self.next_id;
+        self.next_id += 1;
+        id.to_string()
+    }
+}
+
+pub(crate) fn ready<T: Send + 'static>(value: T) -> Future<T> {
+    Box::pin(futures::future::ready(value))
+}
+
+/// The accountant and the trader of the merchants which trade on an `Exchange`. Cheap to
+/// clone, the clones share the exchange.
+#[derive(Clone, Debug)]
+pub struct SharedExchange {
+    exchange: Arc<Mutex<Exchange>>,
+}
+
+impl SharedExchange {
+    pub fn new(exchange: Exchange) -> SharedExchange {
+        SharedExchange {
+            exchange: Arc::new(Mutex::new(exchange)),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, Exchange> {
+        self.exchange.lock().expect("Exchange is poisoned")
+    }
+}
+
+impl Trader for SharedExchange {
+    /// Every order is answered at once from the current book.
+    fn create_order(&self, order: Order) -> Future<Result<Trade, String>> {
+        ready(self.lock().execute(&order))
+    }
+
+    fn delete_order(&self, id: &str) -> Future<Result<(), String>> {
+        ready(self.lock().cancel(id))
+    }
+}
+
+impl Accountant for SharedExchange {
+    fn ask(&self, coin: Coin) -> Future<Result<Currency, String>> {
+        ready(self.lock().currency(coin))
+    }
+
+    fn ask_both(
+        &self,
+        first_coin: Coin,
+        second_coin: Coin,
+    ) -> Future<Result<(Currency, Currency), String>> {
+        let exchange = self.lock();
+        ready(exchange.currency(first_coin).and_then(|first| {
+            exchange.currency(second_coin).map(|second| (first, second))
+        }))
+    }
+
+    /// Fees are charged by the `Exchange` itself.
+    fn calculate_volume(
+        &self,
+        _trading_pair: TradingPair,
+        price: f64,
+        amount: f64,
+    ) -> f64 {
+        price * amount
+    }
+
+    /// There is no tick size, any price is accepted.
+    fn nearest_price(&self, _trading_pair: TradingPair, price: f64) -> f64 {
+        price
+    }
+}
+
+/// A recorded level with a zero price would break the fills of the market buys.
+fn valid_levels(mut levels: Vec<Level>) -> Vec<Level> {
+    levels.retain(|level| level.price > Decimal::ZERO && level.amount > Decimal::ZERO);
+    levels
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn snapshot(
+        time: i64,
+        bids: &[(Decimal, Decimal)],
+        asks: &[(Decimal, Decimal)],
+    ) -> BookSnapshot {
+        let levels = |levels: &[(Decimal, Decimal)]| {
+            levels
+                .iter()
+                .map(|(price, amount)| Level {
+                    price: *price,
+                    amount: *amount,
+                })
+                .collect()
+        };
+        BookSnapshot {
+            time,
+            coins: Coins::TonUsdt.into(),
+            bids: levels(bids),
+            asks: levels(asks),
+        }
+    }
+
+    #[test]
+    fn market_orders() {
+        let mut exchange = Exchange::new(Coins::TonUsdt, dec!(10), dec!(100));
+        exchange.update_book(&snapshot(
+            1,
+            &[(dec!(0.9), dec!(5)), (dec!(0.8), dec!(5))],
+            &[(dec!(1), dec!(5)), (dec!(2), dec!(5))],
+        ));
+        assert_eq!(exchange.mid_price(), Some(dec!(0.95)));
+        let fill = exchange.market(Side::Buy, dec!(10)).unwrap();
+        assert_eq!((fill.price, fill.amount), (dec!(1.5), dec!(10)));
+        assert_eq!(exchange.base(), (dec!(20), dec!(0)));
+        assert_eq!(exchange.quote(), (dec!(85), dec!(0)));
+        assert!(exchange.book(Side::Buy).is_empty());
+        assert!(exchange.market(Side::Buy, dec!(1)).is_err());
+        // Only the depth of the book is sold.
+        let fill = exchange.market(Side::Sell, dec!(15)).unwrap();
+        assert_eq!((fill.price, fill.amount), (dec!(0.85), dec!(10)));
+        assert_eq!(exchange.base(), (dec!(10), dec!(0)));
+        assert_eq!(exchange.fills().len(), 2);
+    }
+
+    #[test]
+    fn limit_orders() {
+        let mut exchange = Exchange::new(Coins::TonUsdt, dec!(10), dec!(100))
+            .with_fees(Fees::flat(dec!(0.01)));
+        let book = |price: Decimal| [(price, dec!(5))];
+        exchange.update_book(&snapshot(1, &book(dec!(0.9)), &book(dec!(1.1))));
+        let buy = exchange.limit(Side::Buy, dec!(1), dec!(10)).unwrap();
+        let sell = exchange.limit(Side::Sell, dec!(1.2), dec!(10)).unwrap();
+        assert_eq!(exchange.quote(), (dec!(89.9), dec!(10.1)));
+        assert_eq!(exchange.base(), (dec!(0), dec!(10)));
+        assert!(exchange.limit(Side::Sell, dec!(1.2), dec!(1)).is_err());
+
+        // The buy order is filled partially at its own price.
+        exchange.update_book(&snapshot(2, &book(dec!(0.9)), &[(dec!(0.95), dec!(4))]));
+        assert_eq!(exchange.orders()[0].amount, dec!(6));
+        assert_eq!(exchange.base(), (dec!(4), dec!(10)));
+        assert!(exchange.book(Side::Buy).is_empty());
+
+        exchange.update_book(&snapshot(3, &[(dec!(1.3), dec!(20))], &book(dec!(1.4))));
+        assert_eq!(exchange.orders().len(), 1);
+        assert_eq!(exchange.quote().0, dec!(89.9) + dec!(12) * dec!(0.99));
+        assert_eq!(exchange.book(Side::Sell)[0].amount, dec!(10));
+
+        exchange.cancel(&buy).unwrap();
+        let quote = dec!(89.9) + dec!(11.88) + dec!(6.06);
+        assert_eq!(exchange.quote(), (quote, dec!(0)));
+        assert!(exchange.cancel(&sell).is_err());
+        assert_eq!(exchange.fills().len(), 2);
+    }
+
+    #[test]
+    fn broken_levels_are_skipped() {
+        let mut exchange = Exchange::new(Coins::TonUsdt, dec!(10), dec!(100));
+        exchange.update_book(&snapshot(
+            1,
+            &[(dec!(0.9), dec!(0)), (dec!(-1), dec!(5))],
+            &[(dec!(0), dec!(5)), (dec!(1), dec!(5))],
+        ));
+        assert!(exchange.book(Side::Sell).is_empty());
+        assert_eq!(exchange.book(Side::Buy).len(), 1);
+        exchange.update_book_side(Side::Buy, vec![Level {
+            price: dec!(0),
+            amount: dec!(5),
+        }], 2);
+        assert!(exchange.book(Side::Buy).is_empty());
+        assert!(exchange.market(Side::Buy, dec!(1)).is_err());
+    }
+}
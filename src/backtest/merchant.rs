@@ -0,0 +1,116 @@
+//! `Merchant` over an `Exchange` which is fed with a `Replay`. Every call is answered at
+//! once from the current book, the book moves only on `SimulatedMerchant::advance`.
+use crate::backtest::exchange::{self, ready, Exchange, SharedExchange};
+use crate::backtest::replay::Replay;
+use crate::decimal::{self, Decimal};
+use agnostic::market::{Accountant, Future, Sniffer, Trader};
+use agnostic::merchant::Merchant;
+use agnostic::order::{Order, OrderWithId};
+use agnostic::trading_pair::TradingPair;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, MutexGuard};
+
+struct Simulation {
+    exchange: SharedExchange,
+    replay: Replay,
+    cursor: AtomicUsize,
+}
+
+impl Sniffer for Simulation {
+    fn all_the_best_orders(
+        &self,
+        trading_pair: TradingPair,
+        count: u32,
+    ) -> Future<Result<Vec<Order>, String>> {
+        let exchange = self.exchange.lock();
+        if trading_pair.coins != exchange.coins {
+            return ready(Err(format!("{:?} is not traded", trading_pair.coins)));
+        }
+        let orders = exchange
+            .book(exchange::book_side(&trading_pair))
+            .iter()
+            .take(count as usize)
+            .map(|level| Order {
+                trading_pair: trading_pair.clone(),
+                price: decimal::to_f64(level.price),
+                amount: decimal::to_f64(level.amount),
+            })
+            .collect();
+        ready(Ok(orders))
+    }
+
+    fn get_my_orders(
+        &self,
+        trading_pair: TradingPair,
+    ) -> Future<Result<Vec<OrderWithId>, String>> {
+        ready(self.exchange.lock().my_orders(&trading_pair))
+    }
+}
+
+/// Cheap to clone, the clones share the exchange and the position in the replay.
+#[derive(Clone)]
+pub struct SimulatedMerchant {
+    id: &'static str,
+    simulation: Arc<Simulation>,
+}
+
+impl SimulatedMerchant {
+    pub fn new(
+        id: &'static str,
+        exchange: Exchange,
+        replay: Replay,
+    ) -> SimulatedMerchant {
+        SimulatedMerchant {
+            id,
+            simulation: Arc::new(Simulation {
+                exchange: SharedExchange::new(exchange),
+                replay,
+                cursor: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Moves to the next book of the replay, `false` once the replay is over.
+    pub fn advance(&self) -> bool {
+        let cursor = self.simulation.cursor.load(Ordering::SeqCst);
+        match self.simulation.replay.snapshots.get(cursor) {
+            Some(snapshot) => {
+                self.exchange().update_book(snapshot);
+                self.simulation.cursor.store(cursor + 1, Ordering::SeqCst);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn exchange(&self) -> MutexGuard<'_, Exchange> {
+        self.simulation.exchange.lock()
+    }
+
+    /// Time of the current book.
+    pub fn time(&self) -> i64 {
+        self.exchange().time()
+    }
+
+    pub fn mid_price(&self) -> Option<Decimal> {
+        self.exchange().mid_price()
+    }
+}
+
+impl Merchant for SimulatedMerchant {
+    fn id(&self) -> &'static str {
+        self.id
+    }
+
+    fn accountant(&self) -> Arc<dyn Accountant> {
+        Arc::new(self.simulation.exchange.clone())
+    }
+
+    fn trader(&self) -> Arc<dyn Trader> {
+        Arc::new(self.simulation.exchange.clone())
+    }
+
+    fn sniffer(&self) -> Arc<dyn Sniffer> {
+        self.simulation.clone()
+    }
+}
@@ -0,0 +1,144 @@
+//! Backtest
+//!
+//! Runs a strategy over recorded order books. Every `SimulatedMerchant` serves the books
+//! of its `Replay` one by one, the strategy makes a single step per book and its trades
+//! are written to a `Bookkeeper` ledger, so the result is read exactly like the one of a
+//! live run.
+pub mod exchange;
+pub mod merchant;
+pub mod replay;
+
+pub use exchange::{Exchange, Fill, RestingOrder, SharedExchange};
+pub use merchant::SimulatedMerchant;
+pub use replay::{BookSnapshot, Level, Replay};
+
+use crate::bookkeeper::{self, Bookkeeper, Coins, TradingResult};
+use crate::decimal::Decimal;
+use crate::error::Error;
+use crate::limit_master::{LimitMaster, OrderEntity};
+use crate::reseller::Reseller;
+use agnostic::trade::Trade;
+use std::future::Future;
+use std::pin::Pin;
+
+pub type Step<'s> =
+    Pin<Box<dyn Future<Output = Result<Vec<OrderEntity<Trade>>, Error>> + 's>>;
+
+/// A strategy driven by the backtest.
+pub trait Strategy {
+    fn kind(&self) -> bookkeeper::Strategy;
+
+    /// Reacts to the current books and returns the trades made since the last step.
+    fn step(&mut self) -> Step<'_>;
+}
+
+/// Reports the fills of the last books and moves my orders.
+impl<'a> Strategy for LimitMaster<'a> {
+    fn kind(&self) -> bookkeeper::Strategy {
+        bookkeeper::Strategy::LimitMaster
+    }
+
+    fn step(&mut self) -> Step<'_> {
+        Box::pin(async move {
+            let fills = self.check_current_orders().await?;
+            let update = self.update_orders().await?;
+            for failure in update.failures.iter() {
+                log::warn!("Failed to update orders: {}", failure);
+            }
+            Ok(fills)
+        })
+    }
+}
+
+/// Resells the trades accepted before the run.
+impl<'a> Strategy for Reseller<'a> {
+    fn kind(&self) -> bookkeeper::Strategy {
+        bookkeeper::Strategy::Reseller
+    }
+
+    fn step(&mut self) -> Step<'_> {
+        Box::pin(self.iterate())
+    }
+}
+
+#[derive(Debug)]
+pub struct Report {
+    /// Number of the books the strategy has reacted to.
+    pub steps: usize,
+    pub result: TradingResult,
+    /// Mid price of the last book, the open position is valued at it.
+    pub mark_price: Option<Decimal>,
+}
+
+impl Report {
+    /// Net realized PnL plus the unrealized one of the open position.
+    pub fn pnl(&self, coins: &Coins) -> Option<Decimal> {
+        let result = self.result.get(coins)?;
+        let unrealized = self
+            .mark_price
+            .map_or(Decimal::ZERO, |mark_price| result.unrealized(mark_price));
+        Some(result.net() + unrealized)
+    }
+}
+
+pub struct Backtest<'m> {
+    merchants: Vec<&'m SimulatedMerchant>,
+    /// Fee of the ledger, the `Exchange` charges its own fees.
+    fee: Decimal,
+}
+
+impl<'m> Backtest<'m> {
+    /// `merchants` are the ones the strategy trades on.
+    pub fn new(merchants: Vec<&'m SimulatedMerchant>) -> Backtest<'m> {
+        Backtest {
+            merchants,
+            fee: Decimal::ZERO,
+        }
+    }
+
+    pub fn with_fee(mut self, fee: Decimal) -> Self {
+        self.fee = fee;
+        self
+    }
+
+    /// Steps until every replay is over. Exchange errors are logged and the strategy
+    /// continues with the next book, the other errors stop the run.
+    pub async fn run(
+        &self,
+        strategy: &mut dyn Strategy,
+        bookkeeper: &mut Bookkeeper,
+    ) -> Result<Report, Error> {
+        let mut steps = 0;
+        loop {
+            let advanced = self
+                .merchants
+                .iter()
+                .fold(false, |advanced, merchant| merchant.advance() || advanced);
+            if !advanced {
+                break;
+            }
+            steps += 1;
+            let trades = match strategy.step().await {
+                Ok(trades) => trades,
+                Err(error) if error.is_transient() => {
+                    log::warn!("Step {} failed: {}", steps, error);
+                    continue;
+                }
+                Err(error) => return Err(error),
+            };
+            let time = self.merchants.iter().map(|merchant| merchant.time()).max();
+            for entity in trades.into_iter() {
+                let mut trade = bookkeeper::Trade::from(entity.order);
+                trade.time = time;
+                trade.merchant_id = Some(entity.merchant_id.to_owned());
+                trade.strategy = Some(strategy.kind());
+                bookkeeper.append(&trade)?;
+            }
+        }
+        Ok(Report {
+            steps,
+            result: bookkeeper.get_trades_result(self.fee)?,
+            mark_price: self.merchants.iter().find_map(|merchant| merchant.mid_price()),
+        })
+    }
+}
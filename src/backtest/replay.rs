@@ -0,0 +1,62 @@
+//! Recorded order books, one JSON snapshot per line.
+use crate::bookkeeper::Coins;
+use crate::decimal::Decimal;
+use crate::error::Error;
+use std::io::Write;
+use std::path::Path;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Level {
+    pub price: Decimal,
+    pub amount: Decimal,
+}
+
+/// Bids are sorted from the highest price and asks from the lowest one.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+pub struct BookSnapshot {
+    /// Unix time in milliseconds.
+    pub time: i64,
+    pub coins: Coins,
+    pub bids: Vec<Level>,
+    pub asks: Vec<Level>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Replay {
+    pub snapshots: Vec<BookSnapshot>,
+}
+
+impl Replay {
+    pub fn new(snapshots: Vec<BookSnapshot>) -> Replay {
+        Replay { snapshots }
+    }
+
+    /// Unlike the ledger, a broken recording is not skipped silently.
+    pub fn load(path: impl AsRef<Path>) -> Result<Replay, Error> {
+        let content = std::fs::read_to_string(path.as_ref())?;
+        let snapshots = content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str::<BookSnapshot>)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Replay { snapshots })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path.as_ref())?);
+        for snapshot in self.snapshots.iter() {
+            serde_json::to_writer(&mut file, snapshot)?;
+            file.write_all(b"\n")?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+}
@@ -1,3 +1,7 @@
+use crate::decimal;
+use crate::error::Error;
+use crate::instrument::Instruments;
+use crate::risk::RiskManager;
 use agnostic::trading_pair::TradingPair;
 use agnostic::merchant::Merchant;
 use agnostic::trade::Trade;
@@ -6,16 +10,28 @@ use std::sync::Arc;
 pub struct BestPriceMarketTrader {
     pub pair: TradingPair,
     pub amount: f64,
+    pub instruments: Instruments,
+    pub risk_manager: RiskManager,
 }
 
 impl BestPriceMarketTrader {
-    pub async fn iterate(&self, merchant: Arc<dyn Merchant>) -> Result<Trade, String> {
+    pub async fn iterate(&self, merchant: Arc<dyn Merchant>) -> Result<Trade, Error> {
         let sniffer = merchant.sniffer();
         let mut best_order = sniffer.all_the_best_orders(self.pair.clone(), 1)
-            .await?
-            .remove(0);
+            .await
+            .map_err(|error| Error::sniffer(merchant.id(), self.pair.clone(), error))?
+            .into_iter()
+            .next()
+            .ok_or_else(|| {
+                Error::sniffer(merchant.id(), self.pair.clone(), "Empty stock".to_owned())
+            })?;
         best_order.amount = self.amount;
+        let best_price = decimal::from_f64(best_order.price);
+        let best_order = self.instruments.normalize(merchant.id(), best_order)?;
+        self.risk_manager.check(merchant.id(), &best_order, Some(best_price))?;
         let trader = merchant.trader();
-        trader.create_order(best_order).await
+        trader.create_order(best_order)
+            .await
+            .map_err(|error| Error::trader(merchant.id(), self.pair.clone(), error))
     }
 }
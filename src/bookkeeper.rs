@@ -1,21 +1,53 @@
+//! Bookkeeper
+//!
+//! Trades are appended to the ledger one JSON record per line, every line is prefixed with
+//! the CRC32 of the record: `<crc32 in hex> <json>`. A torn record at the end of the ledger
+//! is cut off on open, corrupted records in the middle are skipped on read.
+//!
+//! Ledgers of the previous format (JSON records split with `|`) are migrated on open, the
+//! original file is kept with the `legacy` extension.
+use crate::decimal::{self, Decimal};
+use crate::error::Error;
 use agnostic::trade;
 use agnostic::trade::TradeResult;
 use agnostic::order::OrderWithId;
 use agnostic::trading_pair;
+use std::convert::TryFrom;
 use std::io::Read;
 use std::io::Seek;
 use std::io::Write;
+use std::path::PathBuf;
+
+/// When the committed trades are flushed to the disk.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FsyncPolicy {
+    Never,
+    Always,
+    /// Every n-th commit.
+    Every(usize),
+}
+
+impl Default for FsyncPolicy {
+    fn default() -> Self {
+        FsyncPolicy::Always
+    }
+}
 
 pub struct Bookkeeper {
     trades: std::fs::File,
+    path: PathBuf,
+    fsync_policy: FsyncPolicy,
+    unsynced: usize,
 }
 
 impl Bookkeeper {
     const DEAFULT_TRADES_PATH: &'static str = "trades";
     const DEFAULT_EXTENSION: &'static str = "agnostic";
-    const SPLITTER: char = '|';
+    const LEGACY_EXTENSION: &'static str = "legacy";
+    const MIGRATION_EXTENSION: &'static str = "migration";
+    const LEGACY_SPLITTER: char = '|';
 
-    pub fn new() -> std::io::Result<Bookkeeper> {
+    pub fn new() -> Result<Bookkeeper, Error> {
         let time = time::OffsetDateTime::now_utc();
         let filename = format!(
             "{}_{}-{}-{}_{}-{}-{}.{}",
@@ -28,13 +60,12 @@ impl Bookkeeper {
             time.second(),
             Self::DEFAULT_EXTENSION
         );
-        Self::open(std::path::PathBuf::from(filename))
+        Self::open(PathBuf::from(filename))
     }
 
-    pub fn local() -> Option<std::io::Result<Bookkeeper>> {
+    pub fn local() -> Option<Result<Bookkeeper, Error>> {
         if let Ok(entries) = std::fs::read_dir(".") {
-            for entry in entries {
-                let entry = entry.unwrap();
+            for entry in entries.filter_map(Result::ok) {
                 if let Some(extension) = entry.path().extension().map_or(None, |extension| Some(extension.to_owned())) {
                     if extension == Self::DEFAULT_EXTENSION {
                         return Some(Self::open(entry.path()));
@@ -47,113 +78,422 @@ impl Bookkeeper {
         }
     }
 
-    pub fn open(filename: std::path::PathBuf) -> std::io::Result<Bookkeeper> {
+    pub fn open(filename: PathBuf) -> Result<Bookkeeper, Error> {
         let trades = std::fs::OpenOptions::new()
             .write(true)
             .read(true)
             .create(true)
             .open(&filename)?;
-        Ok(Bookkeeper { trades })
-    }
-
-    pub fn commit_trade(&mut self, trade: trade::Trade) {
-        let trade: Trade = trade.into();
-        let mut trade = serde_json::to_string(&trade).expect("Serialization error");
-        trade.push(Self::SPLITTER);
-        self.trades
-            .seek(std::io::SeekFrom::End(0))
-            .expect("Failed to seek to end");
-        self.trades
-            .write_all(trade.as_bytes())
-            .expect("Failed to commit order");
-    }
-
-    pub fn get_all_trades(&mut self) -> Vec<Trade> {
-        let mut result = String::with_capacity(100);
-        self.trades
-            .seek(std::io::SeekFrom::Start(0))
-            .expect("Failed to seek to start");
-        self.trades
-            .read_to_string(&mut result)
-            .expect("Failed to read trades.");
-        result
-            .split(Self::SPLITTER)
-            .filter_map(
-                |trade_json| match serde_json::from_str::<Trade>(&trade_json) {
-                    Ok(trade) => Some(trade),
-                    Err(_error) => None,
-                },
-            )
-            .collect()
+        let mut bookkeeper = Bookkeeper {
+            trades,
+            path: filename,
+            fsync_policy: FsyncPolicy::default(),
+            unsynced: 0,
+        };
+        let content = bookkeeper.read_content()?;
+        if is_legacy(&content) {
+            bookkeeper.migrate(&content)?;
+        } else {
+            bookkeeper.repair(&content)?;
+        }
+        Ok(bookkeeper)
     }
 
-    pub fn get_trades_result(&mut self) -> TradingResult {
-        TradingResult::aggregate(self.get_all_trades())
+    pub fn with_fsync_policy(mut self, fsync_policy: FsyncPolicy) -> Self {
+        self.fsync_policy = fsync_policy;
+        self
     }
 
-    pub fn clear_trades(&mut self) {
-        self.trades.set_len(0).unwrap();
+    pub fn commit_trade(
+        &mut self,
+        trade: trade::Trade,
+        merchant_id: &str,
+        strategy: Strategy,
+    ) -> Result<(), Error> {
+        let mut trade: Trade = trade.into();
+        trade.time = Some(now_millis());
+        trade.merchant_id = Some(merchant_id.to_owned());
+        trade.strategy = Some(strategy);
+        self.append(&trade)
+    }
+
+    pub fn append(&mut self, trade: &Trade) -> Result<(), Error> {
+        let record = encode_record(trade)?;
+        self.trades.seek(std::io::SeekFrom::End(0))?;
+        self.trades.write_all(record.as_bytes())?;
+        self.unsynced += 1;
+        let sync = match self.fsync_policy {
+            FsyncPolicy::Never => false,
+            FsyncPolicy::Always => true,
+            FsyncPolicy::Every(commits) => self.unsynced >= commits,
+        };
+        if sync {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    pub fn sync(&mut self) -> Result<(), Error> {
+        self.trades.sync_data()?;
+        self.unsynced = 0;
+        Ok(())
+    }
+
+    /// Corrupted records are skipped.
+    pub fn get_all_trades(&mut self) -> Result<Vec<Trade>, Error> {
+        let content = self.read_content()?;
+        Ok(content
+            .split(|byte| *byte == b'\n')
+            .enumerate()
+            .filter(|(_index, line)| !line.is_empty())
+            .filter_map(|(index, line)| match decode_record(line) {
+                Ok(trade) => Some(trade),
+                Err(error) => {
+                    log::warn!("Record {} of {:?} is skipped: {}", index, self.path, error);
+                    None
+                }
+            })
+            .collect())
+    }
+
+    /// `fee` is the fraction of every trade paid to the exchange.
+    pub fn get_trades_result(&mut self, fee: Decimal) -> Result<TradingResult, Error> {
+        Ok(TradingResult::aggregate(self.get_all_trades()?, fee))
+    }
+
+    pub fn get_trades_result_in(
+        &mut self,
+        window: TimeWindow,
+        fee: Decimal,
+    ) -> Result<TradingResult, Error> {
+        let trades = self
+            .get_all_trades()?
+            .into_iter()
+            .filter(|trade| window.contains(trade))
+            .collect();
+        Ok(TradingResult::aggregate(trades, fee))
+    }
+
+    pub fn clear_trades(&mut self) -> Result<(), Error> {
+        self.trades.set_len(0)?;
+        self.sync()
+    }
+
+    fn read_content(&mut self) -> Result<Vec<u8>, Error> {
+        let mut content = Vec::with_capacity(100);
+        self.trades.seek(std::io::SeekFrom::Start(0))?;
+        self.trades.read_to_end(&mut content)?;
+        Ok(content)
+    }
+
+    /// Cuts off the record which was not completely written.
+    fn repair(&mut self, content: &[u8]) -> Result<(), Error> {
+        let length = content
+            .iter()
+            .rposition(|byte| *byte == b'\n')
+            .map_or(0, |position| position + 1);
+        if length < content.len() {
+            log::warn!(
+                "Torn record of {} bytes is removed from {:?}",
+                content.len() - length,
+                self.path);
+            self.trades.set_len(length as u64)?;
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    fn migrate(&mut self, content: &[u8]) -> Result<(), Error> {
+        let trades = read_legacy_trades(&String::from_utf8_lossy(content));
+        log::info!("Migrating {} trades of {:?}", trades.len(), self.path);
+        let mut records = String::with_capacity(content.len());
+        for trade in trades.iter() {
+            records.push_str(&encode_record(trade)?);
+        }
+        let migration_path = self.path.with_extension(Self::MIGRATION_EXTENSION);
+        let mut migration = std::fs::File::create(&migration_path)?;
+        migration.write_all(records.as_bytes())?;
+        migration.sync_all()?;
+        std::fs::copy(&self.path, self.path.with_extension(Self::LEGACY_EXTENSION))?;
+        std::fs::rename(&migration_path, &self.path)?;
+        self.trades = std::fs::OpenOptions::new()
+            .write(true)
+            .read(true)
+            .open(&self.path)?;
+        Ok(())
     }
 }
 
-#[derive(Debug)]
-pub struct TradingResult {
-    sold: f64,
-    bought: f64,
+/// Reads the trades of the previous ledger format, unreadable chunks are skipped.
+pub fn read_legacy_trades(content: &str) -> Vec<Trade> {
+    content
+        .split(Bookkeeper::LEGACY_SPLITTER)
+        .filter(|trade_json| !trade_json.trim().is_empty())
+        .filter_map(
+            |trade_json| match serde_json::from_str::<Trade>(&trade_json) {
+                Ok(trade) => Some(trade),
+                Err(error) => {
+                    log::warn!("Legacy record is skipped: {}", error);
+                    None
+                }
+            },
+        )
+        .collect()
 }
 
-impl TradingResult {
-    pub fn aggregate(trades: Vec<Trade>) -> TradingResult {
-        let (sold, bought) =
-            trades
-                .into_iter()
-                .fold((0f64, 0f64), |acc, order| match order.side {
-                    Side::Buy => (acc.0, acc.1 + order.amount),
-                    Side::Sell => (acc.0 + order.amount, acc.1),
-                });
-        TradingResult { sold, bought }
+fn is_legacy(content: &[u8]) -> bool {
+    content.iter().find(|byte| !byte.is_ascii_whitespace()) == Some(&b'{')
+}
+
+fn encode_record(trade: &Trade) -> Result<String, Error> {
+    let json = serde_json::to_string(trade)?;
+    Ok(format!("{:08x} {}\n", crc32fast::hash(json.as_bytes()), json))
+}
+
+fn decode_record(line: &[u8]) -> Result<Trade, String> {
+    let line = std::str::from_utf8(line).map_err(|error| error.to_string())?;
+    let splitter = line.find(' ').ok_or_else(|| "No checksum".to_owned())?;
+    let (checksum, json) = (&line[..splitter], &line[splitter + 1..]);
+    let checksum = u32::from_str_radix(checksum, 16).map_err(|error| error.to_string())?;
+    if crc32fast::hash(json.as_bytes()) != checksum {
+        return Err("Checksum mismatch".to_owned());
     }
+    serde_json::from_str(json).map_err(|error| error.to_string())
 }
 
-impl std::fmt::Display for TradingResult {
+pub(crate) fn now_millis() -> i64 {
+    (time::OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000) as i64
+}
+
+/// Unix time range in milliseconds, `from` is inclusive and `to` is exclusive.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeWindow {
+    pub from: i64,
+    pub to: i64,
+}
+
+impl TimeWindow {
+    /// The window which ends now.
+    pub fn last(duration: std::time::Duration) -> TimeWindow {
+        let to = now_millis();
+        TimeWindow {
+            from: to - duration.as_millis() as i64,
+            to,
+        }
+    }
+
+    /// Trades without time are recorded before the time was tracked and never match.
+    pub fn contains(&self, trade: &Trade) -> bool {
+        trade.time.map_or(false, |time| self.from <= time && time < self.to)
+    }
+}
+
+/// Result of a single pair. Positions are signed, prices and PnL are in the quote coin.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PairResult {
+    pub sold: Decimal,
+    pub bought: Decimal,
+    pub base_position: Decimal,
+    pub quote_position: Decimal,
+    /// Average price of the open base position.
+    pub average_price: Decimal,
+    pub realized: Decimal,
+    pub fees: Decimal,
+}
+
+impl PairResult {
+    /// Trades reduce the open position at its average price.
+    pub fn apply(
+        &mut self,
+        side: &Side,
+        price: Decimal,
+        amount: Decimal,
+        fee: Decimal,
+    ) {
+        if amount.is_zero() {
+            return;
+        }
+        let signed_amount = match side {
+            Side::Buy => {
+                self.bought += amount;
+                amount
+            }
+            Side::Sell => {
+                self.sold += amount;
+                -amount
+            }
+        };
+        let position = self.base_position;
+        let new_position = position + signed_amount;
+        if position.is_zero() || position.signum() == signed_amount.signum() {
+            self.average_price = (self.average_price * position.abs() + price * amount)
+                / new_position.abs();
+        } else {
+            let closed = amount.min(position.abs());
+            self.realized += (price - self.average_price) * closed * position.signum();
+            if new_position.is_zero() {
+                self.average_price = Decimal::ZERO;
+            } else if new_position.signum() != position.signum() {
+                self.average_price = price;
+            }
+        }
+        self.base_position = new_position;
+        let trade_fee = price * amount * fee;
+        self.fees += trade_fee;
+        self.quote_position -= signed_amount * price + trade_fee;
+    }
+
+    pub fn unrealized(&self, mark_price: Decimal) -> Decimal {
+        (mark_price - self.average_price) * self.base_position
+    }
+
+    /// Realized PnL after fees.
+    pub fn net(&self) -> Decimal {
+        self.realized - self.fees
+    }
+}
+
+impl std::fmt::Display for PairResult {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Sold: {} | Bought: {} | Profit: {}",
+            "Sold: {} | Bought: {} | Base: {} | Quote: {} | Average price: {} | Realized: {} | Fees: {} | Net: {}",
             self.sold,
             self.bought,
-            self.bought - self.sold
+            self.base_position,
+            self.quote_position,
+            self.average_price,
+            self.realized,
+            self.fees,
+            self.net(),
         )
     }
 }
 
+#[derive(Debug, Default)]
+pub struct TradingResult {
+    pub pairs: std::collections::HashMap<Coins, PairResult>,
+}
+
+impl TradingResult {
+    /// Trades are expected in the order they were committed.
+    pub fn aggregate(trades: Vec<Trade>, fee: Decimal) -> TradingResult {
+        let mut result = TradingResult::default();
+        for trade in trades.into_iter() {
+            result
+                .pairs
+                .entry(trade.coins)
+                .or_default()
+                .apply(&trade.side, trade.price, trade.amount, fee);
+        }
+        result
+    }
+
+    pub fn get(&self, coins: &Coins) -> Option<&PairResult> {
+        self.pairs.get(coins)
+    }
+}
+
+impl std::fmt::Display for TradingResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (coins, result) in self.pairs.iter() {
+            writeln!(f, "{}: {}", coins, result)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug, Clone)]
 pub struct Trade {
     pub id: String,
     pub coins: Coins,
     pub side: Side,
     pub target: Target,
-    pub price: f64,
-    pub amount: f64,
+    /// Serialized as a string, ledgers with float prices and amounts are still loaded.
+    pub price: Decimal,
+    pub amount: Decimal,
+    /// Unix time in milliseconds.
+    #[serde(default)]
+    pub time: Option<i64>,
+    #[serde(default)]
+    pub merchant_id: Option<String>,
+    #[serde(default)]
+    pub strategy: Option<Strategy>,
 }
 
+/// The strategy which produced a trade.
+#[derive(serde::Serialize, serde::Deserialize, Hash, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Strategy {
+    Reseller,
+    LimitMaster,
+    BestPriceMarketTrader,
+    Arbitrage,
+}
+
+/// Any base/quote pair. It is stored as `BASE/QUOTE`, the legacy `TonUsdt` is loaded as
+/// `TON/USDT`.
 #[derive(serde::Serialize, serde::Deserialize, Hash, Eq, PartialEq, Debug, Clone)]
-pub enum Coins {
-    TonUsdt,
+#[serde(try_from = "String", into = "String")]
+pub struct Coins {
+    pub base: String,
+    pub quote: String,
+}
+
+impl Coins {
+    const SPLITTER: char = '/';
+
+    pub fn new(base: impl Into<String>, quote: impl Into<String>) -> Self {
+        Coins {
+            base: base.into(),
+            quote: quote.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for Coins {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}{}", self.base, Self::SPLITTER, self.quote)
+    }
+}
+
+impl From<Coins> for String {
+    fn from(coins: Coins) -> Self {
+        coins.to_string()
+    }
+}
+
+impl TryFrom<String> for Coins {
+    type Error = String;
+
+    fn try_from(coins: String) -> Result<Self, Self::Error> {
+        if coins == "TonUsdt" {
+            return Ok(Coins::new("TON", "USDT"));
+        }
+        let mut split = coins.split(Self::SPLITTER);
+        match (split.next(), split.next(), split.next()) {
+            (Some(base), Some(quote), None) if !base.is_empty() && !quote.is_empty() => {
+                Ok(Coins::new(base, quote))
+            }
+            _ => Err(format!("Invalid coins {}", coins)),
+        }
+    }
 }
 
 impl From<trading_pair::Coins> for Coins {
     fn from(coins: trading_pair::Coins) -> Self {
         match coins {
-            trading_pair::Coins::TonUsdt => Coins::TonUsdt,
+            trading_pair::Coins::TonUsdt => Coins::new("TON", "USDT"),
         }
     }
 }
 
-impl From<Coins> for trading_pair::Coins {
-    fn from(coins: Coins) -> Self {
-        match coins {
-            Coins::TonUsdt => trading_pair::Coins::TonUsdt,
+impl TryFrom<Coins> for trading_pair::Coins {
+    type Error = Error;
+
+    fn try_from(coins: Coins) -> Result<Self, Self::Error> {
+        match (coins.base.as_str(), coins.quote.as_str()) {
+            ("TON", "USDT") => Ok(trading_pair::Coins::TonUsdt),
+            _ => Err(Error::configuration(format!("Unsupported coins {}", coins))),
         }
     }
 }
@@ -188,6 +528,15 @@ pub enum Target {
     Limit,
 }
 
+impl From<trading_pair::Target> for Target {
+    fn from(target: trading_pair::Target) -> Self {
+        match target {
+            trading_pair::Target::Market => Target::Market,
+            trading_pair::Target::Limit => Target::Limit,
+        }
+    }
+}
+
 impl From<trade::Trade> for Trade {
     fn from(trade: trade::Trade) -> Trade {
         let (id, coins, side, target, price, amount) = match trade {
@@ -213,20 +562,25 @@ impl From<trade::Trade> for Trade {
             coins: coins.into(),
             side: side.into(),
             target,
-            price,
-            amount,
+            price: decimal::from_f64(price),
+            amount: decimal::from_f64(amount),
+            time: None,
+            merchant_id: None,
+            strategy: None,
         }
     }
 }
 
-impl From<Trade> for trade::Trade {
-    fn from(trade: Trade) -> trade::Trade {
+impl TryFrom<Trade> for trade::Trade {
+    type Error = Error;
+
+    fn try_from(trade: Trade) -> Result<trade::Trade, Self::Error> {
         let id = trade.id;
-        let coins = trade.coins.into();
+        let coins = trading_pair::Coins::try_from(trade.coins)?;
         let side = trade.side.into();
-        let amount = trade.amount;
-        let price = trade.price;
-        match trade.target {
+        let amount = decimal::to_f64(trade.amount);
+        let price = decimal::to_f64(trade.price);
+        Ok(match trade.target {
             Target::Market => trade::Trade::Market(TradeResult {
                 id,
                 trading_pair: trading_pair::TradingPair {
@@ -247,13 +601,14 @@ impl From<Trade> for trade::Trade {
                 price,
                 amount,
             }),
-        }
+        })
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use rust_decimal_macros::dec;
 
     #[test]
     fn test() {
@@ -268,14 +623,149 @@ mod test {
             amount: 100f64,
         });
         let mut bookkeeper = Bookkeeper::new().expect("Failed to create bookkeeper");
-        bookkeeper.clear_trades();
-        let orders = bookkeeper.get_all_trades();
+        bookkeeper.clear_trades().expect("Failed to clear trades");
+        let orders = bookkeeper.get_all_trades().expect("Failed to read trades");
         assert_eq!(orders.len(), 0, "Invalid length");
-        bookkeeper.commit_trade(trade.clone());
-        let orders = bookkeeper.get_all_trades();
+        bookkeeper
+            .commit_trade(trade.clone(), "Test", Strategy::Reseller)
+            .expect("Failed to commit trade");
+        let orders = bookkeeper.get_all_trades().expect("Failed to read trades");
         assert_eq!(orders.len(), 1, "Invalid length");
-        bookkeeper.commit_trade(trade);
-        let orders = bookkeeper.get_all_trades();
+        assert_eq!(orders[0].merchant_id.as_deref(), Some("Test"));
+        assert_eq!(orders[0].strategy, Some(Strategy::Reseller));
+        assert!(orders[0].time.is_some());
+        bookkeeper
+            .commit_trade(trade, "Test", Strategy::LimitMaster)
+            .expect("Failed to commit trade");
+        let orders = bookkeeper.get_all_trades().expect("Failed to read trades");
         assert_eq!(orders.len(), 2, "Invalid length");
     }
+
+    fn ledger_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn torn_and_corrupted_records() {
+        let path = ledger_path("open_midas_torn_and_corrupted_records.agnostic");
+        let mut bookkeeper = Bookkeeper::open(path.clone())
+            .expect("Failed to open bookkeeper")
+            .with_fsync_policy(FsyncPolicy::Every(2));
+        for time in 1..=3 {
+            bookkeeper
+                .append(&trade(Side::Buy, dec!(1), dec!(1), time))
+                .expect("Failed to append trade");
+        }
+        drop(bookkeeper);
+        let mut content = std::fs::read(&path).expect("Failed to read ledger");
+        let second_record = content.iter().position(|byte| *byte == b'\n').unwrap() + 1;
+        // Corrupt the price of the second record and tear a fourth record.
+        let price = second_record
+            + content[second_record..]
+                .windows(9)
+                .position(|window| window == b"\"price\":\"")
+                .unwrap();
+        content[price + 9] = b'7';
+        content.extend_from_slice(b"0badf00d {\"id\":");
+        std::fs::write(&path, &content).expect("Failed to write ledger");
+
+        let mut bookkeeper = Bookkeeper::open(path.clone()).expect("Failed to open bookkeeper");
+        let trades = bookkeeper.get_all_trades().expect("Failed to read trades");
+        assert_eq!(
+            trades.iter().map(|trade| trade.time).collect::<Vec<_>>(),
+            vec![Some(1), Some(3)]);
+        bookkeeper
+            .append(&trade(Side::Sell, dec!(1), dec!(1), 4))
+            .expect("Failed to append trade");
+        let trades = bookkeeper.get_all_trades().expect("Failed to read trades");
+        assert_eq!(trades.len(), 3);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn legacy_ledger() {
+        let path = ledger_path("open_midas_legacy_ledger.agnostic");
+        let legacy = r#"{"id":"1","coins":"TonUsdt","side":"Sell","target":"Market","price":33.0,"amount":100.0}|{"id":"2","coins":"TonUsdt","side":"Buy","target":"Limit","price":32.0,"amount":100.0}|"#;
+        std::fs::write(&path, legacy).expect("Failed to write ledger");
+        let mut bookkeeper = Bookkeeper::open(path.clone()).expect("Failed to open bookkeeper");
+        let trades = bookkeeper.get_all_trades().expect("Failed to read trades");
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[1].id, "2");
+        let legacy_path = path.with_extension(Bookkeeper::LEGACY_EXTENSION);
+        assert_eq!(std::fs::read_to_string(&legacy_path).expect("No legacy ledger"), legacy);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&legacy_path);
+    }
+
+    #[test]
+    fn legacy_trade() {
+        let trade = r#"{"id":"1337","coins":"TonUsdt","side":"Sell","target":"Market","price":33.0,"amount":100.0}"#;
+        let trade: Trade = serde_json::from_str(trade).expect("Failed to load legacy trade");
+        assert_eq!(trade.coins, Coins::new("TON", "USDT"));
+        assert_eq!(trade.time, None);
+        assert_eq!(trade.merchant_id, None);
+        assert_eq!(trade.strategy, None);
+        assert_eq!(trade.price, dec!(33));
+    }
+
+    fn trade(side: Side, price: Decimal, amount: Decimal, time: i64) -> Trade {
+        Trade {
+            id: time.to_string(),
+            coins: Coins::new("TON", "USDT"),
+            side,
+            target: Target::Market,
+            price,
+            amount,
+            time: Some(time),
+            merchant_id: None,
+            strategy: None,
+        }
+    }
+
+    #[test]
+    fn trading_result() {
+        let trades = vec![
+            trade(Side::Buy, dec!(1), dec!(10), 1),
+            trade(Side::Buy, dec!(2), dec!(10), 2),
+            trade(Side::Sell, dec!(3), dec!(15), 3),
+            trade(Side::Sell, dec!(1), dec!(10), 4),
+        ];
+        let coins = Coins::new("TON", "USDT");
+        let result = TradingResult::aggregate(trades[..3].to_vec(), dec!(0.001));
+        let pair = result.get(&coins).expect("No result");
+        assert_eq!(pair.base_position, dec!(5));
+        assert_eq!(pair.average_price, dec!(1.5));
+        assert_eq!(pair.realized, dec!(22.5));
+        assert_eq!(pair.unrealized(dec!(2)), dec!(2.5));
+        assert_eq!(pair.fees, dec!(0.075));
+        assert_eq!(pair.quote_position, dec!(45) - dec!(30) - dec!(0.075));
+
+        let result = TradingResult::aggregate(trades.clone(), Decimal::ZERO);
+        let pair = result.get(&coins).expect("No result");
+        assert_eq!(pair.base_position, dec!(-5));
+        assert_eq!(pair.average_price, dec!(1));
+        assert_eq!(pair.realized, dec!(20));
+
+        let window = TimeWindow { from: 3, to: 5 };
+        let trades = trades.into_iter().filter(|trade| window.contains(trade)).collect();
+        let result = TradingResult::aggregate(trades, Decimal::ZERO);
+        let pair = result.get(&coins).expect("No result");
+        assert_eq!(pair.sold, dec!(25));
+        assert_eq!(pair.bought, Decimal::ZERO);
+    }
+
+    #[test]
+    fn coins() {
+        let legacy: Coins = serde_json::from_str("\"TonUsdt\"").expect("Invalid legacy coins");
+        assert_eq!(legacy, Coins::new("TON", "USDT"));
+        assert_eq!(legacy, Coins::from(agnostic::trading_pair::Coins::TonUsdt));
+        let coins = Coins::new("BTC", "USDT");
+        let json = serde_json::to_string(&coins).expect("Failed to serialize coins");
+        assert_eq!(json, "\"BTC/USDT\"");
+        assert_eq!(serde_json::from_str::<Coins>(&json).expect("Invalid coins"), coins);
+        assert!(serde_json::from_str::<Coins>("\"BTCUSDT\"").is_err());
+        assert!(agnostic::trading_pair::Coins::try_from(coins).is_err());
+    }
 }
@@ -1,19 +1,20 @@
+use crate::decimal::{self, Decimal};
 use agnostic::order::Order;
 
 #[derive(Clone, Copy, Debug)]
 pub struct AmountCalculator {
-    pub min_amount_threshold: f64,
-    pub fee: f64,
+    pub min_amount_threshold: Decimal,
+    pub fee: Decimal,
 }
 
 #[derive(PartialEq, Debug)]
 pub enum Amount {
-    PriceBased(f64),
-    BalanceBased(f64),
+    PriceBased(Decimal),
+    BalanceBased(Decimal),
 }
 
 impl Amount {
-    pub fn value(&self) -> f64 {
+    pub fn value(&self) -> Decimal {
         match self {
             Amount::PriceBased(value) => *value,
             Amount::BalanceBased(value) => *value,
@@ -23,26 +24,26 @@ impl Amount {
 
 #[derive(Debug)]
 pub struct Balance {
-    pub amount: f64,
-    pub fee: f64,
+    pub amount: Decimal,
+    pub fee: Decimal,
 }
 
 impl Balance {
-    pub fn with_fee(&self) -> f64 {
-        self.amount * (1.0 - self.fee)
+    pub fn with_fee(&self) -> Decimal {
+        self.amount * (Decimal::ONE - self.fee)
     }
 
-    pub fn raw(&self) -> f64 {
+    pub fn raw(&self) -> Decimal {
         self.amount
     }
 }
 
 impl AmountCalculator {
     pub fn new(
-        min_amount_threshold: f64,
-        fee: f64,
+        min_amount_threshold: Decimal,
+        fee: Decimal,
     ) -> Option<AmountCalculator> {
-        if fee >= 1.0 || fee < 0.0 {
+        if fee >= Decimal::ONE || fee < Decimal::ZERO {
             None
         } else {
             Some(AmountCalculator {
@@ -52,27 +53,46 @@ impl AmountCalculator {
         }
     }
 
+    /// Keeps the larger of the own fee and the merchant's `fee` aside of the balance.
+    pub fn balance(&self, amount: Decimal, fee: Decimal) -> Balance {
+        Balance {
+            amount,
+            fee: self.fee.max(fee),
+        }
+    }
+
+    /// A calculator which keeps the merchant's `fee` aside if it is larger than the own one.
+    pub fn with_merchant_fee(&self, fee: Decimal) -> AmountCalculator {
+        AmountCalculator {
+            fee: self.fee.max(fee),
+            ..*self
+        }
+    }
+
     pub fn calculate(
         &self,
         direct_order: &Order,
-        direct_coin_balance: f64,
+        direct_coin_balance: Decimal,
         reversed_order: &Order,
-        reversed_coin_balance: f64,
-    ) -> Option<(f64, f64)> {
+        reversed_coin_balance: Decimal,
+    ) -> Option<(Decimal, Decimal)> {
+        let direct_price = decimal::from_f64(direct_order.price);
+        let reversed_price = decimal::from_f64(reversed_order.price);
         let balance_amount = reversed_coin_balance.min(direct_coin_balance);
-        let min_order_amount = direct_order.amount.min(reversed_order.amount);
+        let min_order_amount = decimal::from_f64(direct_order.amount)
+            .min(decimal::from_f64(reversed_order.amount));
         let result = if balance_amount <= min_order_amount {
-            let max_amount = balance_amount * (1.0 - self.fee);
-            if direct_order.price > reversed_order.price {
-                (reversed_order.price * max_amount / direct_order.price, max_amount)
+            let max_amount = balance_amount * (Decimal::ONE - self.fee);
+            if direct_price > reversed_price {
+                (reversed_price * max_amount / direct_price, max_amount)
             } else {
-                (max_amount, direct_order.price * max_amount / reversed_order.price)
+                (max_amount, direct_price * max_amount / reversed_price)
             }
         } else {
-            if direct_order.price > reversed_order.price {
-                (reversed_order.price * min_order_amount / direct_order.price, min_order_amount)
+            if direct_price > reversed_price {
+                (reversed_price * min_order_amount / direct_price, min_order_amount)
             } else {
-                (min_order_amount, direct_order.price * min_order_amount / reversed_order.price)
+                (min_order_amount, direct_price * min_order_amount / reversed_price)
             }
         };
         match (result.0 > self.min_amount_threshold, result.1 > self.min_amount_threshold) {
@@ -83,7 +103,7 @@ impl AmountCalculator {
 
     pub fn evaluate(
         &self,
-        order_amount: f64,
+        order_amount: Decimal,
         balance: &Balance,
     ) -> Option<Amount> {
         let balance_with_fee = balance.with_fee();
@@ -108,6 +128,7 @@ mod test {
     use super::*;
     use agnostic::order::Order;
     use agnostic::trading_pair::{TradingPair, Target, Side, Coins};
+    use rust_decimal_macros::dec;
 
     fn default_trading_pair(side: Side) -> TradingPair {
         TradingPair {
@@ -131,22 +152,23 @@ mod test {
             amount: 110f64,
             price: 2f64,
         };
-        let amount_calculator = AmountCalculator::new(0.1, 0.1).expect("Invalid fee");
+        let amount_calculator =
+            AmountCalculator::new(dec!(0.1), dec!(0.1)).expect("Invalid fee");
         let calculated = amount_calculator.calculate(
             &direct_order,
-            1000f64,
+            dec!(1000),
             &reversed_order,
-            1000f64);
-        assert_eq!(calculated, Some((100f64, 50f64)));
+            dec!(1000));
+        assert_eq!(calculated, Some((dec!(100), dec!(50))));
         {
             direct_order.price = 2f64;
             reversed_order.price = 1f64;
             let calculated = amount_calculator.calculate(
                 &direct_order,
-                1000f64,
+                dec!(1000),
                 &reversed_order,
-                1000f64);
-            assert_eq!(calculated, Some((50f64, 100f64)));
+                dec!(1000));
+            assert_eq!(calculated, Some((dec!(50), dec!(100))));
             direct_order.price = 1f64;
             reversed_order.price = 2f64;
         }
@@ -166,48 +188,48 @@ mod test {
             amount: 100f64,
             price: 1f64,
         };
-        let calculator = AmountCalculator::new(0.1, 0.1).unwrap();
+        let calculator = AmountCalculator::new(dec!(0.1), dec!(0.1)).unwrap();
         let amount = calculator.calculate(
             &direct_order,
-            100f64,
+            dec!(100),
             &revesed_order,
-            100f64);
-        assert_eq!(amount, Some((90f64, 90f64)));
+            dec!(100));
+        assert_eq!(amount, Some((dec!(90), dec!(90))));
         direct_order.amount = 0.1;
         let amount = calculator.calculate(
             &direct_order,
-            100f64,
+            dec!(100),
             &revesed_order,
-            100f64);
+            dec!(100));
         assert_eq!(amount, None);
         direct_order.amount = 100f64;
         revesed_order.amount = 0.0;
         let amount = calculator.calculate(
             &direct_order,
-            100f64,
+            dec!(100),
             &revesed_order,
-            100f64);
+            dec!(100));
         assert_eq!(amount, None);
         revesed_order.amount = 100f64;
         let amount = calculator.calculate(
             &direct_order,
-            10f64,
+            dec!(10),
             &revesed_order,
-            20f64);
-        assert_eq!(amount, Some((9.0, 9f64)));
+            dec!(20));
+        assert_eq!(amount, Some((dec!(9), dec!(9))));
     }
 
     #[test]
     fn evaluate() {
-        let calculator = AmountCalculator::new(0.1, 0.1).unwrap();
+        let calculator = AmountCalculator::new(dec!(0.1), dec!(0.1)).unwrap();
         let amount = calculator.evaluate(
-            100.0,
+            dec!(100),
             &Balance {
-                amount: 100.0,
+                amount: dec!(100),
                 fee: calculator.fee,
             }
         );
-        assert_eq!(amount, Some(Amount::BalanceBased(90.0)))
+        assert_eq!(amount, Some(Amount::BalanceBased(dec!(90))))
     }
 }
 
@@ -0,0 +1,81 @@
+use crate::decimal::Decimal;
+use agnostic::trading_pair::Target;
+use std::collections::HashMap;
+
+/// Fees are fractions of the traded volume, e.g. `0.001` is 0.1%.
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub struct Fees {
+    /// Fee of the limit orders.
+    pub maker: Decimal,
+    /// Fee of the market orders.
+    pub taker: Decimal,
+}
+
+impl Fees {
+    pub fn flat(fee: Decimal) -> Fees {
+        Fees {
+            maker: fee,
+            taker: fee,
+        }
+    }
+
+    pub fn get(&self, target: Target) -> Decimal {
+        match target {
+            Target::Limit => self.maker,
+            Target::Market => self.taker,
+        }
+    }
+}
+
+/// Fees of every merchant. Merchants without their own fees pay the default ones.
+#[derive(Default, Clone, Debug)]
+pub struct FeeModel {
+    pub default: Fees,
+    merchants: HashMap<String, Fees>,
+}
+
+impl FeeModel {
+    pub fn new(default: Fees) -> FeeModel {
+        FeeModel {
+            default,
+            merchants: HashMap::new(),
+        }
+    }
+
+    pub fn with_merchant(mut self, merchant_id: &str, fees: Fees) -> Self {
+        self.merchants.insert(merchant_id.to_owned(), fees);
+        self
+    }
+
+    pub fn fees(&self, merchant_id: &str) -> Fees {
+        self.merchants
+            .get(merchant_id)
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    pub fn fee(&self, merchant_id: &str, target: Target) -> Decimal {
+        self.fees(merchant_id).get(target)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn fee_model() {
+        let model = FeeModel::new(Fees::flat(dec!(0.002))).with_merchant(
+            "Maker",
+            Fees {
+                maker: dec!(0),
+                taker: dec!(0.001),
+            },
+        );
+        assert_eq!(model.fee("Maker", Target::Limit), dec!(0));
+        assert_eq!(model.fee("Maker", Target::Market), dec!(0.001));
+        assert_eq!(model.fee("Unknown", Target::Limit), dec!(0.002));
+        assert_eq!(model.fees("Unknown"), Fees::flat(dec!(0.002)));
+    }
+}
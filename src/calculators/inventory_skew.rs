@@ -0,0 +1,107 @@
+use crate::decimal::Decimal;
+use agnostic::trading_pair::Side;
+
+/// Shifts the quotes to bring the inventory of the base coin back to the target. Being
+/// long both prices go down and the buy amount shrinks, being short both prices go up and
+/// the sell amount shrinks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InventorySkew {
+    /// Base coin amount the inventory reverts to.
+    pub target: Decimal,
+    /// Relative price shift per base coin away from the target.
+    pub coefficient: Decimal,
+    /// Limit of the relative price shift.
+    pub max_shift: Decimal,
+    /// No buy orders are placed at or above the limit, the buy amount shrinks to it.
+    pub max_inventory: Option<Decimal>,
+    /// No sell orders are placed at or below the limit, the sell amount shrinks to it.
+    pub min_inventory: Option<Decimal>,
+}
+
+impl InventorySkew {
+    pub fn new(
+        target: Decimal,
+        coefficient: Decimal,
+        max_shift: Decimal,
+    ) -> InventorySkew {
+        InventorySkew {
+            target,
+            coefficient,
+            max_shift,
+            max_inventory: None,
+            min_inventory: None,
+        }
+    }
+
+    pub fn with_limits(mut self, min_inventory: Decimal, max_inventory: Decimal) -> Self {
+        self.min_inventory = Some(min_inventory);
+        self.max_inventory = Some(max_inventory);
+        self
+    }
+
+    /// Relative price shift, positive when the inventory is above the target.
+    pub fn shift(&self, inventory: Decimal) -> Decimal {
+        (self.coefficient * (inventory - self.target))
+            .max(-self.max_shift)
+            .min(self.max_shift)
+    }
+
+    pub fn price(&self, price: Decimal, inventory: Decimal) -> Decimal {
+        price * (Decimal::ONE - self.shift(inventory))
+    }
+
+    /// Zero once the position limit of `side` is reached.
+    pub fn amount(&self, side: Side, amount: Decimal, inventory: Decimal) -> Decimal {
+        let (limit, distance) = match side {
+            Side::Buy => (self.max_inventory, inventory - self.target),
+            Side::Sell => (self.min_inventory, self.target - inventory),
+        };
+        if !self.allows(side, inventory) {
+            return Decimal::ZERO;
+        }
+        match limit {
+            Some(limit) if distance > Decimal::ZERO => {
+                let range = (limit - self.target).abs();
+                let factor = Decimal::ONE - distance / range;
+                amount * factor.max(Decimal::ZERO)
+            }
+            _ => amount,
+        }
+    }
+
+    /// Whether orders of `side` may be placed with the `inventory`.
+    pub fn allows(&self, side: Side, inventory: Decimal) -> bool {
+        match side {
+            Side::Buy => self.max_inventory.map_or(true, |limit| inventory < limit),
+            Side::Sell => self.min_inventory.map_or(true, |limit| inventory > limit),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn inventory_skew() {
+        let skew = InventorySkew::new(dec!(100), dec!(0.001), dec!(0.05))
+            .with_limits(dec!(50), dec!(150));
+        assert_eq!(skew.shift(dec!(100)), dec!(0));
+        assert_eq!(skew.shift(dec!(120)), dec!(0.02));
+        assert_eq!(skew.shift(dec!(10)), dec!(-0.05));
+        assert_eq!(skew.price(dec!(1), dec!(120)), dec!(0.98));
+        assert_eq!(skew.price(dec!(1), dec!(80)), dec!(1.02));
+
+        assert_eq!(skew.amount(Side::Buy, dec!(10), dec!(125)), dec!(5));
+        assert_eq!(skew.amount(Side::Sell, dec!(10), dec!(125)), dec!(10));
+        assert_eq!(skew.amount(Side::Sell, dec!(10), dec!(75)), dec!(5));
+        assert_eq!(skew.amount(Side::Buy, dec!(10), dec!(150)), dec!(0));
+        assert_eq!(skew.amount(Side::Sell, dec!(10), dec!(50)), dec!(0));
+        assert!(skew.allows(Side::Sell, dec!(150)));
+        assert!(!skew.allows(Side::Buy, dec!(150)));
+
+        let unlimited = InventorySkew::new(dec!(100), dec!(0.001), dec!(0.05));
+        assert_eq!(unlimited.amount(Side::Buy, dec!(10), dec!(1000)), dec!(10));
+    }
+}
@@ -0,0 +1,112 @@
+use crate::decimal::Decimal;
+use agnostic::trading_pair::Side;
+
+/// A level of a ladder. `offset` moves the price away from the market on top of the
+/// profit of the price calculator, `fraction` is the part of the amount placed at it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rung {
+    pub offset: Decimal,
+    pub fraction: Decimal,
+}
+
+impl Rung {
+    pub fn price(&self, side: Side, price: Decimal) -> Decimal {
+        match side {
+            Side::Buy => price * (Decimal::ONE - self.offset),
+            Side::Sell => price * (Decimal::ONE + self.offset),
+        }
+    }
+
+    pub fn amount(&self, amount: Decimal) -> Decimal {
+        amount * self.fraction
+    }
+}
+
+/// Rungs are ordered from the market outwards. The fractions should not exceed one in
+/// total, so the whole ladder fits into the amount of a single order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ladder {
+    pub rungs: Vec<Rung>,
+}
+
+impl Default for Ladder {
+    /// The single order at the price of the price calculator.
+    fn default() -> Self {
+        Ladder {
+            rungs: vec![Rung {
+                offset: Decimal::ZERO,
+                fraction: Decimal::ONE,
+            }],
+        }
+    }
+}
+
+impl Ladder {
+    /// The first rung is placed at the price of the price calculator, the gap to the
+    /// next rung starts with `step` and is multiplied by `spacing` on every level. The
+    /// size of every next rung is multiplied by `growth`.
+    pub fn geometric(
+        levels: usize,
+        step: Decimal,
+        spacing: Decimal,
+        growth: Decimal,
+    ) -> Ladder {
+        if levels == 0 {
+            return Ladder { rungs: Vec::new() };
+        }
+        let mut offsets = Vec::with_capacity(levels);
+        let mut weights = Vec::with_capacity(levels);
+        let (mut offset, mut gap, mut weight) = (Decimal::ZERO, step, Decimal::ONE);
+        for _ in 0..levels {
+            offsets.push(offset);
+            weights.push(weight);
+            offset += gap;
+            gap *= spacing;
+            weight *= growth;
+        }
+        let total: Decimal = weights.iter().sum();
+        let mut fractions: Vec<Decimal> =
+            weights.iter().map(|weight| weight / total).collect();
+        // The last rung takes the rest, so the fractions are exactly one in total.
+        let rest = Decimal::ONE - fractions[..levels - 1].iter().sum::<Decimal>();
+        fractions[levels - 1] = rest;
+        Ladder {
+            rungs: offsets
+                .into_iter()
+                .zip(fractions.into_iter())
+                .map(|(offset, fraction)| Rung { offset, fraction })
+                .collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rungs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rungs.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn geometric() {
+        let ladder = Ladder::geometric(3, dec!(0.01), dec!(2), dec!(2));
+        assert_eq!(ladder.len(), 3);
+        let offsets: Vec<_> = ladder.rungs.iter().map(|rung| rung.offset).collect();
+        assert_eq!(offsets, vec![dec!(0), dec!(0.01), dec!(0.03)]);
+        let total: Decimal = ladder.rungs.iter().map(|rung| rung.fraction).sum();
+        assert_eq!(total, Decimal::ONE);
+        assert!(ladder.rungs[0].fraction < ladder.rungs[1].fraction);
+        assert!(ladder.rungs[1].fraction < ladder.rungs[2].fraction);
+        assert_eq!(ladder.rungs[1].amount(dec!(70)).round_dp(10), dec!(20));
+        assert_eq!(ladder.rungs[2].price(Side::Buy, dec!(100)), dec!(97));
+        assert_eq!(ladder.rungs[2].price(Side::Sell, dec!(100)), dec!(103));
+        assert_eq!(Ladder::geometric(1, dec!(0.01), dec!(2), dec!(2)), Ladder::default());
+        assert!(Ladder::geometric(0, dec!(0.01), dec!(2), dec!(2)).is_empty());
+    }
+}
@@ -1,6 +1,14 @@
 pub mod amount_calculator;
 pub mod profit_calculator;
 pub mod price_calculator;
+pub mod fee_model;
+pub mod ladder;
+pub mod inventory_skew;
+pub mod spread_model;
 
 pub use amount_calculator::AmountCalculator;
 pub use profit_calculator::ProfitCalculator;
+pub use fee_model::{FeeModel, Fees};
+pub use ladder::{Ladder, Rung};
+pub use inventory_skew::InventorySkew;
+pub use spread_model::{SpreadModel, VolatilitySpread};
@@ -1,38 +1,69 @@
+use crate::calculators::fee_model::Fees;
+use crate::decimal::Decimal;
+
 pub struct PriceCalculator {
-    pub profit: f64,
+    pub profit: Decimal,
 }
 
 impl PriceCalculator {
-    pub fn low(&self, price: f64) -> f64 {
-        price * (1f64 - self.profit)
+    pub fn low(&self, price: Decimal) -> Decimal {
+        self.low_with_fees(price, Fees::default())
+    }
+
+    pub fn high(&self, price: Decimal) -> Decimal {
+        self.high_with_fees(price, Fees::default())
     }
 
-    pub fn high(&self, price: f64) -> f64 {
-        price * (1.0 + self.profit)
+    /// The limit order pays the maker fee and the market `price` the taker one, so the
+    /// profit is net of both.
+    pub fn low_with_fees(&self, price: Decimal, fees: Fees) -> Decimal {
+        price * (Decimal::ONE - self.profit) * (Decimal::ONE - fees.taker)
+            / (Decimal::ONE + fees.maker)
+    }
+
+    pub fn high_with_fees(&self, price: Decimal, fees: Fees) -> Decimal {
+        price * (Decimal::ONE + self.profit) * (Decimal::ONE + fees.taker)
+            / (Decimal::ONE - fees.maker)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use rust_decimal_macros::dec;
 
     #[test]
     fn low() {
         let calculator = PriceCalculator {
-            profit: 0.1f64
+            profit: dec!(0.1)
         };
-        let price = 100f64;
-        let expected_price = 90f64;
-        assert!((calculator.low(price) - expected_price).abs() < 1e-5)
+        let price = dec!(100);
+        let expected_price = dec!(90);
+        assert_eq!(calculator.low(price), expected_price)
     }
 
     #[test]
     fn high() {
         let calculator = PriceCalculator {
-            profit: 0.1f64
+            profit: dec!(0.1)
+        };
+        let price = dec!(100);
+        let expected_price = dec!(110);
+        assert_eq!(calculator.high(price), expected_price)
+    }
+
+    #[test]
+    fn with_fees() {
+        let calculator = PriceCalculator {
+            profit: dec!(0.1)
+        };
+        let fees = Fees {
+            maker: dec!(0.01),
+            taker: dec!(0.02),
         };
-        let price = 100f64;
-        let expected_price = 110.0;
-        assert!((calculator.high(price) - expected_price).abs() < 1e-5)
+        let low = calculator.low_with_fees(dec!(100), fees);
+        assert_eq!(low, dec!(90) * dec!(0.98) / dec!(1.01));
+        let high = calculator.high_with_fees(dec!(100), fees);
+        assert_eq!(high, dec!(110) * dec!(1.02) / dec!(0.99));
     }
 }
@@ -1,27 +1,47 @@
+use crate::decimal::{self, Decimal};
 use agnostic::order::Order;
 
+/// The profit is net of the fees of both orders.
 #[derive(Default, Copy, Clone, Debug)]
 pub struct ProfitCalculator {
+    pub sell_fee: Decimal,
+    pub buy_fee: Decimal,
 }
 
 impl ProfitCalculator {
+    pub fn new(sell_fee: Decimal, buy_fee: Decimal) -> ProfitCalculator {
+        ProfitCalculator { sell_fee, buy_fee }
+    }
+
     pub fn calculate(
         &self,
         direct_order: &Order,
         reversed_order: &Order,
-    ) -> Option<f64> {
-        let direct = direct_order.price;
-        let reversed = reversed_order.price;
+    ) -> Option<Decimal> {
+        let direct = decimal::from_f64(direct_order.price);
+        let reversed = decimal::from_f64(reversed_order.price);
         self.evaluate(direct, reversed)
     }
 
-    pub fn evaluate(&self, sell_price: f64, buy_price: f64) -> Option<f64> {
-        if sell_price >= buy_price {
-            Some(1.0 - buy_price / sell_price)
+    pub fn evaluate(&self, sell_price: Decimal, buy_price: Decimal) -> Option<Decimal> {
+        let sell_price = self.net_sell_price(sell_price);
+        let buy_price = self.net_buy_price(buy_price);
+        if sell_price >= buy_price && sell_price > Decimal::ZERO {
+            Some(Decimal::ONE - buy_price / sell_price)
         } else {
             None
         }
     }
+
+    /// What is received per coin sold.
+    pub fn net_sell_price(&self, price: Decimal) -> Decimal {
+        price * (Decimal::ONE - self.sell_fee)
+    }
+
+    /// What is paid per coin bought.
+    pub fn net_buy_price(&self, price: Decimal) -> Decimal {
+        price * (Decimal::ONE + self.buy_fee)
+    }
 }
 
 #[cfg(test)]
@@ -29,6 +49,7 @@ mod test {
     use super::*;
     use agnostic::order::Order;
     use agnostic::trading_pair::{TradingPair, Target, Side, Coins};
+    use rust_decimal_macros::dec;
 
     #[test]
     fn profit_calculator() {
@@ -50,14 +71,26 @@ mod test {
         };
         let calculator = ProfitCalculator::default();
         let amount = calculator.calculate(&direct_order, &revesed_order);
-        assert_eq!(amount, Some(0.0));
+        assert_eq!(amount, Some(dec!(0)));
         revesed_order.price = 2f64;
         let amount = calculator.calculate(&direct_order, &revesed_order);
         assert_eq!(amount, None);
         revesed_order.price = 1f64;
         direct_order.price = 2f64;
         let amount = calculator.calculate(&direct_order, &revesed_order);
-        assert_eq!(amount, Some(0.5));
+        assert_eq!(amount, Some(dec!(0.5)));
         direct_order.price = 1f64;
     }
+
+    #[test]
+    fn profit_with_fees() {
+        let calculator = ProfitCalculator::new(dec!(0.01), dec!(0.01));
+        assert_eq!(calculator.evaluate(dec!(1), dec!(1)), None);
+        let profit = calculator.evaluate(dec!(2), dec!(1)).expect("No profit");
+        assert_eq!(profit, dec!(1) - dec!(1.01) / dec!(1.98));
+        assert_eq!(
+            ProfitCalculator::default().evaluate(dec!(2), dec!(1)),
+            Some(dec!(0.5))
+        );
+    }
 }
@@ -0,0 +1,117 @@
+use crate::calculators::price_calculator::PriceCalculator;
+use crate::decimal::Decimal;
+use std::collections::VecDeque;
+
+/// The profit of the quotes, which may depend on the observed market.
+pub trait SpreadModel {
+    /// Relative offset of the quotes from the market price.
+    fn profit(&self) -> Decimal;
+
+    /// The mid price of the market, observed before every update of the quotes.
+    fn observe(&mut self, _mid_price: Decimal) {}
+}
+
+/// The fixed profit.
+impl SpreadModel for PriceCalculator {
+    fn profit(&self) -> Decimal {
+        self.profit
+    }
+}
+
+/// The profit follows the realized volatility of the last mid prices, measured as the
+/// mean absolute relative change between the observations.
+#[derive(Clone, Debug)]
+pub struct VolatilitySpread {
+    mid_prices: VecDeque<Decimal>,
+    window: usize,
+    /// Profit per unit of volatility.
+    pub multiplier: Decimal,
+    pub min_profit: Decimal,
+    pub max_profit: Decimal,
+}
+
+impl VolatilitySpread {
+    /// At least two prices are kept in the `window`.
+    pub fn new(
+        window: usize,
+        multiplier: Decimal,
+        min_profit: Decimal,
+        max_profit: Decimal,
+    ) -> VolatilitySpread {
+        let window = window.max(2);
+        VolatilitySpread {
+            mid_prices: VecDeque::with_capacity(window),
+            window,
+            multiplier,
+            min_profit,
+            max_profit,
+        }
+    }
+
+    /// `None` until two prices are observed.
+    pub fn volatility(&self) -> Option<Decimal> {
+        if self.mid_prices.len() < 2 {
+            return None;
+        }
+        let changes = self
+            .mid_prices
+            .iter()
+            .zip(self.mid_prices.iter().skip(1))
+            .map(|(previous, current)| ((current - previous) / previous).abs());
+        let count = Decimal::from(self.mid_prices.len() - 1);
+        Some(changes.sum::<Decimal>() / count)
+    }
+}
+
+impl SpreadModel for VolatilitySpread {
+    /// Without enough observations the quotes are kept as far as possible.
+    fn profit(&self) -> Decimal {
+        match self.volatility() {
+            Some(volatility) => (volatility * self.multiplier)
+                .max(self.min_profit)
+                .min(self.max_profit),
+            None => self.max_profit,
+        }
+    }
+
+    fn observe(&mut self, mid_price: Decimal) {
+        if mid_price <= Decimal::ZERO {
+            return;
+        }
+        if self.mid_prices.len() == self.window {
+            self.mid_prices.pop_front();
+        }
+        self.mid_prices.push_back(mid_price);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn fixed() {
+        let mut calculator = PriceCalculator { profit: dec!(0.1) };
+        calculator.observe(dec!(100));
+        assert_eq!(SpreadModel::profit(&calculator), dec!(0.1));
+    }
+
+    #[test]
+    fn volatility_spread() {
+        let mut spread = VolatilitySpread::new(3, dec!(2), dec!(0.01), dec!(0.1));
+        assert_eq!(spread.profit(), dec!(0.1));
+        spread.observe(dec!(100));
+        spread.observe(dec!(101));
+        assert_eq!(spread.volatility(), Some(dec!(0.01)));
+        assert_eq!(spread.profit(), dec!(0.02));
+        spread.observe(dec!(101));
+        spread.observe(dec!(101));
+        assert_eq!(spread.volatility(), Some(dec!(0)));
+        assert_eq!(spread.profit(), dec!(0.01));
+        spread.observe(dec!(150));
+        assert_eq!(spread.profit(), dec!(0.1));
+        spread.observe(dec!(0));
+        assert_eq!(spread.mid_prices.len(), 3);
+    }
+}
@@ -0,0 +1,391 @@
+//! Circuit breaker
+//!
+//! Stops the trading once something goes wrong: a merchant keeps failing my orders, the
+//! ledger falls too far from its peak or the order book of a merchant is not refreshed
+//! anymore. The trader errors and the books are seen through the merchants wrapped with
+//! `CircuitBreaker::wrap`, the drawdown is refreshed with `CircuitBreaker::sync`.
+//!
+//! Once tripped the wrapped traders refuse new orders, while the cancellations still go
+//! through, and the `Engine` deletes my orders and skips its cycles. The breaker stays
+//! tripped until it is reset by hand.
+use crate::bookkeeper::{Bookkeeper, TradingResult};
+use crate::decimal::Decimal;
+use crate::error::Error;
+use crate::limit_master::MerchantId;
+use agnostic::market::{Accountant, Future, Sniffer, Trader};
+use agnostic::merchant::Merchant;
+use agnostic::order::{Order, OrderWithId};
+use agnostic::trade::Trade;
+use agnostic::trading_pair::TradingPair;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Why the breaker has tripped.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Trip {
+    TraderErrors {
+        merchant_id: MerchantId,
+        errors: usize,
+    },
+    Drawdown {
+        drawdown: Decimal,
+        max_drawdown: Decimal,
+    },
+    StaleBook {
+        merchant_id: MerchantId,
+        age: Duration,
+        max_age: Duration,
+    },
+    /// Tripped by hand with `CircuitBreaker::kill`.
+    Manual { reason: String },
+}
+
+impl std::fmt::Display for Trip {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Trip::TraderErrors {
+                merchant_id,
+                errors,
+            } => write!(f, "{} trader errors in a row on {}", errors, merchant_id),
+            Trip::Drawdown {
+                drawdown,
+                max_drawdown,
+            } => write!(f, "Drawdown {} reached the limit {}", drawdown, max_drawdown),
+            Trip::StaleBook {
+                merchant_id,
+                age,
+                max_age,
+            } => write!(
+                f,
+                "Order book of {} is {:?} old, the limit is {:?}",
+                merchant_id, age, max_age
+            ),
+            Trip::Manual { reason } => write!(f, "Killed: {}", reason),
+        }
+    }
+}
+
+/// `None` disables the corresponding condition.
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct TripConditions {
+    /// Max failed orders and cancellations of a single merchant in a row.
+    pub max_trader_errors: Option<usize>,
+    /// Max fall of the net PnL of the ledger from its peak since the last reset.
+    pub max_drawdown: Option<Decimal>,
+    /// Max time since the last non empty order book of a wrapped merchant.
+    pub max_book_age: Option<Duration>,
+}
+
+#[derive(Default, Debug)]
+struct State {
+    trip: Option<Trip>,
+    trader_errors: HashMap<MerchantId, usize>,
+    /// Times of the last order books per merchant.
+    books: HashMap<MerchantId, Instant>,
+    peak_pnl: Option<Decimal>,
+}
+
+impl State {
+    /// The first trip is kept until the reset.
+    fn trip(&mut self, trip: Trip) {
+        if self.trip.is_none() {
+            log::error!("Circuit breaker is tripped: {}", trip);
+            self.trip = Some(trip);
+        }
+    }
+}
+
+/// Cheap to clone, the clones share the state, so the breaker may be reset from another
+/// thread while the `Engine` runs.
+#[derive(Default, Clone, Debug)]
+pub struct CircuitBreaker {
+    conditions: TripConditions,
+    /// Fee of the ledger.
+    fee: Decimal,
+    state: Arc<Mutex<State>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(conditions: TripConditions) -> CircuitBreaker {
+        CircuitBreaker {
+            conditions,
+            ..CircuitBreaker::default()
+        }
+    }
+
+    pub fn with_fee(mut self, fee: Decimal) -> Self {
+        self.fee = fee;
+        self
+    }
+
+    pub fn conditions(&self) -> &TripConditions {
+        &self.conditions
+    }
+
+    pub fn trip(&self) -> Option<Trip> {
+        self.lock().trip.clone()
+    }
+
+    pub fn is_tripped(&self) -> bool {
+        self.lock().trip.is_some()
+    }
+
+    /// The kill switch, trips the breaker whatever the conditions are.
+    pub fn kill(&self, reason: impl Into<String>) {
+        self.lock().trip(Trip::Manual {
+            reason: reason.into(),
+        });
+    }
+
+    /// Clears the trip and the error counters. The ages of the books and the peak of the
+    /// PnL start over.
+    pub fn reset(&self) {
+        let mut state = self.lock();
+        if let Some(trip) = state.trip.take() {
+            log::info!("Circuit breaker is reset after: {}", trip);
+        }
+        state.trader_errors.clear();
+        state.peak_pnl = None;
+        let now = Instant::now();
+        for time in state.books.values_mut() {
+            *time = now;
+        }
+    }
+
+    pub fn record_trader_success(&self, merchant_id: MerchantId) {
+        self.lock().trader_errors.remove(merchant_id);
+    }
+
+    pub fn record_trader_error(&self, merchant_id: MerchantId) {
+        let mut state = self.lock();
+        let errors = state.trader_errors.entry(merchant_id).or_default();
+        *errors += 1;
+        let errors = *errors;
+        match self.conditions.max_trader_errors {
+            Some(max_trader_errors) if errors >= max_trader_errors => {
+                state.trip(Trip::TraderErrors {
+                    merchant_id,
+                    errors,
+                })
+            }
+            _ => (),
+        }
+    }
+
+    pub fn record_book(&self, merchant_id: MerchantId) {
+        self.lock().books.insert(merchant_id, Instant::now());
+    }
+
+    /// Reads the net PnL from the ledger and trips on the drawdown from its peak.
+    pub fn sync(&self, bookkeeper: &mut Bookkeeper) -> Result<(), Error> {
+        let trades = bookkeeper.get_all_trades()?;
+        let pnl: Decimal = TradingResult::aggregate(trades, self.fee)
+            .pairs
+            .values()
+            .map(|result| result.net())
+            .sum();
+        let mut state = self.lock();
+        let peak_pnl = state.peak_pnl.map_or(pnl, |peak_pnl| peak_pnl.max(pnl));
+        state.peak_pnl = Some(peak_pnl);
+        if let Some(max_drawdown) = self.conditions.max_drawdown {
+            let drawdown = peak_pnl - pnl;
+            if drawdown > max_drawdown {
+                state.trip(Trip::Drawdown {
+                    drawdown,
+                    max_drawdown,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Trips on the stale books and returns the current trip.
+    pub fn check(&self) -> Option<Trip> {
+        let mut state = self.lock();
+        if let Some(max_age) = self.conditions.max_book_age {
+            let stale = state
+                .books
+                .iter()
+                .map(|(merchant_id, time)| (*merchant_id, time.elapsed()))
+                .find(|(_merchant_id, age)| *age > max_age);
+            if let Some((merchant_id, age)) = stale {
+                state.trip(Trip::StaleBook {
+                    merchant_id,
+                    age,
+                    max_age,
+                });
+            }
+        }
+        state.trip.clone()
+    }
+
+    /// `merchant` with the trader and the sniffer watched by the breaker. The age of its
+    /// book starts now.
+    pub fn wrap<'m>(&self, merchant: &'m dyn Merchant) -> GuardedMerchant<'m> {
+        self.record_book(merchant.id());
+        GuardedMerchant {
+            merchant,
+            trader: Arc::new(GuardedTrader {
+                trader: merchant.trader(),
+                merchant_id: merchant.id(),
+                breaker: self.clone(),
+            }),
+            sniffer: Arc::new(GuardedSniffer {
+                sniffer: merchant.sniffer(),
+                merchant_id: merchant.id(),
+                breaker: self.clone(),
+            }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("Circuit breaker state is poisoned")
+    }
+}
+
+pub struct GuardedTrader {
+    trader: Arc<dyn Trader>,
+    merchant_id: MerchantId,
+    breaker: CircuitBreaker,
+}
+
+impl GuardedTrader {
+    fn watch<T: Send + 'static>(
+        &self,
+        future: Future<Result<T, String>>,
+    ) -> Future<Result<T, String>> {
+        let (merchant_id, breaker) = (self.merchant_id, self.breaker.clone());
+        Box::pin(async move {
+            let result = future.await;
+            match &result {
+                Ok(_) => breaker.record_trader_success(merchant_id),
+                Err(_) => breaker.record_trader_error(merchant_id),
+            }
+            result
+        })
+    }
+}
+
+impl Trader for GuardedTrader {
+    /// Refused while the breaker is tripped.
+    fn create_order(&self, order: Order) -> Future<Result<Trade, String>> {
+        if let Some(trip) = self.breaker.trip() {
+            let error = format!("Circuit breaker is tripped: {}", trip);
+            return Box::pin(futures::future::ready(Err(error)));
+        }
+        self.watch(self.trader.create_order(order))
+    }
+
+    fn delete_order(&self, id: &str) -> Future<Result<(), String>> {
+        self.watch(self.trader.delete_order(id))
+    }
+}
+
+pub struct GuardedSniffer {
+    sniffer: Arc<dyn Sniffer>,
+    merchant_id: MerchantId,
+    breaker: CircuitBreaker,
+}
+
+impl Sniffer for GuardedSniffer {
+    fn all_the_best_orders(
+        &self,
+        trading_pair: TradingPair,
+        count: u32,
+    ) -> Future<Result<Vec<Order>, String>> {
+        let future = self.sniffer.all_the_best_orders(trading_pair, count);
+        let (merchant_id, breaker) = (self.merchant_id, self.breaker.clone());
+        Box::pin(async move {
+            let response = future.await;
+            if response.as_ref().map_or(false, |orders| !orders.is_empty()) {
+                breaker.record_book(merchant_id);
+            }
+            response
+        })
+    }
+
+    fn get_my_orders(
+        &self,
+        trading_pair: TradingPair,
+    ) -> Future<Result<Vec<OrderWithId>, String>> {
+        self.sniffer.get_my_orders(trading_pair)
+    }
+}
+
+/// The merchant as is, except for the guarded trader and sniffer.
+pub struct GuardedMerchant<'m> {
+    merchant: &'m dyn Merchant,
+    trader: Arc<GuardedTrader>,
+    sniffer: Arc<GuardedSniffer>,
+}
+
+impl<'m> Merchant for GuardedMerchant<'m> {
+    fn id(&self) -> &'static str {
+        self.merchant.id()
+    }
+
+    fn accountant(&self) -> Arc<dyn Accountant> {
+        self.merchant.accountant()
+    }
+
+    fn trader(&self) -> Arc<dyn Trader> {
+        self.trader.clone()
+    }
+
+    fn sniffer(&self) -> Arc<dyn Sniffer> {
+        self.sniffer.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn trader_errors_trip_until_reset() {
+        let breaker = CircuitBreaker::new(TripConditions {
+            max_trader_errors: Some(2),
+            ..TripConditions::default()
+        });
+        breaker.record_trader_error("first");
+        breaker.record_trader_success("first");
+        breaker.record_trader_error("first");
+        breaker.record_trader_error("second");
+        assert_eq!(breaker.check(), None);
+        breaker.record_trader_error("first");
+        let trip = Trip::TraderErrors {
+            merchant_id: "first",
+            errors: 2,
+        };
+        assert_eq!(breaker.check(), Some(trip.clone()));
+        // The first trip is kept.
+        breaker.kill("Test");
+        assert_eq!(breaker.clone().trip(), Some(trip));
+
+        breaker.reset();
+        assert!(!breaker.is_tripped());
+        breaker.record_trader_error("second");
+        assert_eq!(breaker.check(), None);
+    }
+
+    #[test]
+    fn stale_book() {
+        let breaker = CircuitBreaker::new(TripConditions {
+            max_book_age: Some(Duration::from_millis(50)),
+            ..TripConditions::default()
+        });
+        breaker.record_book("first");
+        assert_eq!(breaker.check(), None);
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(matches!(
+            breaker.check(),
+            Some(Trip::StaleBook {
+                merchant_id: "first",
+                ..
+            })
+        ));
+        breaker.reset();
+        assert_eq!(breaker.check(), None);
+    }
+}
@@ -0,0 +1,43 @@
+//! Decimal
+//!
+//! Prices and amounts are kept as decimals, while the exchanges are called with `f64`. A
+//! float is converted through its shortest representation, so `0.1` becomes exactly `0.1`.
+//! Decimals are stored as strings, the stored floats are still loaded.
+use rust_decimal::prelude::ToPrimitive;
+pub use rust_decimal::Decimal;
+use std::str::FromStr;
+
+/// Not finite floats become zero.
+pub fn from_f64(value: f64) -> Decimal {
+    Decimal::from_str(&value.to_string())
+        .ok()
+        .or_else(|| Decimal::from_f64_retain(value))
+        .unwrap_or_default()
+}
+
+pub fn to_f64(value: Decimal) -> f64 {
+    value.to_f64().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn conversion() {
+        assert_eq!(from_f64(0.1), dec!(0.1));
+        assert_eq!(from_f64(0.1 + 0.2), dec!(0.30000000000000004));
+        assert_eq!(from_f64(f64::NAN), Decimal::ZERO);
+        assert_eq!(to_f64(dec!(0.49)), 0.49);
+    }
+
+    #[test]
+    fn serde() {
+        let decimal: Decimal = serde_json::from_str("0.49").expect("Failed to load float");
+        assert_eq!(decimal, dec!(0.49));
+        let decimal: Decimal = serde_json::from_str("\"0.49\"").expect("Failed to load");
+        assert_eq!(decimal, dec!(0.49));
+        assert_eq!(serde_json::to_string(&decimal).unwrap(), "\"0.49\"");
+    }
+}
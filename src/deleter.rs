@@ -1,3 +1,5 @@
+use crate::error::Error;
+use crate::fan_out::FanOut;
 use agnostic::{
     merchant::Merchant,
     trading_pair::{TradingPair, Coins, Side, Target}
@@ -5,21 +7,40 @@ use agnostic::{
 
 #[derive(Debug, Default, Clone)]
 pub struct Deleter {
+    pub fan_out: FanOut,
 }
 
 impl Deleter {
+    /// Deletes my orders on all the merchants at once. The first failed merchant is reported.
     pub async fn delete(
         &self,
         merchants: &[&dyn Merchant],
         trading_pair: TradingPair,
-    ) -> Result<(), String> {
-        for merchant in merchants {
-            let sniffer = merchant.sniffer();
-            let my_orders = sniffer.get_my_orders(trading_pair.clone()).await?;
-            let trader = merchant.trader();
-            for order in my_orders.iter() {
-                trader.delete_order(&order.id).await?;
-            }
+    ) -> Result<(), Error> {
+        let results = self
+            .fan_out
+            .join(merchants.iter().map(|merchant| self.delete_on(*merchant, trading_pair.clone())))
+            .await;
+        results.into_iter().collect()
+    }
+
+    async fn delete_on(
+        &self,
+        merchant: &dyn Merchant,
+        trading_pair: TradingPair,
+    ) -> Result<(), Error> {
+        let sniffer = merchant.sniffer();
+        let my_orders = self
+            .fan_out
+            .call(sniffer.get_my_orders(trading_pair.clone()))
+            .await
+            .map_err(|error| Error::sniffer(merchant.id(), trading_pair.clone(), error))?;
+        let trader = merchant.trader();
+        for order in my_orders.iter() {
+            self.fan_out
+                .call(trader.delete_order(&order.id))
+                .await
+                .map_err(|error| Error::trader(merchant.id(), trading_pair.clone(), error))?;
         }
         Ok(())
     }
@@ -28,7 +49,7 @@ impl Deleter {
         &self,
         merchants: &[&dyn Merchant],
         coins: Coins,
-    ) -> Result<(), String> {
+    ) -> Result<(), Error> {
         let trading_pair = TradingPair {
             coins,
             side: Side::Sell,
@@ -0,0 +1,267 @@
+//! Engine
+//!
+//! Runs the whole cycle of a market maker: the fills of my limit orders are recorded by
+//! the `Bookkeeper` and accepted by the `Reseller`, the `Reseller` sells what is
+//! profitable and the `LimitMaster` moves my limit orders. The state is saved after every
+//! step, so a crash loses at most the step in flight. A tripped `CircuitBreaker` stops
+//! the cycle before its next step, my limit orders are deleted instead.
+use crate::bookkeeper::{Bookkeeper, Strategy};
+use crate::circuit_breaker::{CircuitBreaker, Trip};
+use crate::error::Error;
+use crate::limit_master::{LimitMaster, OrderEntity, Update};
+use crate::limit_master_saver::LimitMasterSaver;
+use crate::reseller::Reseller;
+use crate::risk::RiskManager;
+use crate::reseller_saver::ResellerSaver;
+use agnostic::trade::Trade;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Stops the `Engine` after the cycle in flight. The handle may be sent to other threads.
+#[derive(Clone, Debug, Default)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+}
+
+/// What a single cycle has done.
+#[derive(Clone, Debug, Default)]
+pub struct Cycle {
+    pub fills: Vec<OrderEntity<Trade>>,
+    pub resales: Vec<OrderEntity<Trade>>,
+    pub update: Update,
+    /// The trip which has stopped the cycle.
+    pub trip: Option<Trip>,
+}
+
+pub struct Engine<'a> {
+    limit_master: LimitMaster<'a>,
+    reseller: Reseller<'a>,
+    bookkeeper: Bookkeeper,
+    reseller_saver: ResellerSaver,
+    limit_master_saver: Option<LimitMasterSaver>,
+    risk_manager: Option<RiskManager>,
+    circuit_breaker: Option<CircuitBreaker>,
+    /// My orders are deleted once per trip.
+    halted: bool,
+    interval: Duration,
+    shutdown: Shutdown,
+}
+
+impl<'a> Engine<'a> {
+    const SHUTDOWN_POLL: Duration = Duration::from_millis(100);
+
+    pub fn new(
+        limit_master: LimitMaster<'a>,
+        reseller: Reseller<'a>,
+        bookkeeper: Bookkeeper,
+        reseller_saver: ResellerSaver,
+    ) -> Self {
+        Engine {
+            limit_master,
+            reseller,
+            bookkeeper,
+            reseller_saver,
+            limit_master_saver: None,
+            risk_manager: None,
+            circuit_breaker: None,
+            halted: false,
+            interval: Duration::from_secs(10),
+            shutdown: Shutdown::default(),
+        }
+    }
+
+    /// Time between the starts of two cycles.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// My limit orders are saved as well, so they can be resumed after a restart.
+    pub fn with_limit_master_saver(mut self, saver: LimitMasterSaver) -> Self {
+        self.limit_master_saver = Some(saver);
+        self
+    }
+
+    /// The positions and the daily loss of `risk_manager` are synced with the ledger
+    /// after every commit, its resting orders with the orders of the `LimitMaster`. The
+    /// same manager should be shared with the strategies.
+    pub fn with_risk_manager(mut self, risk_manager: RiskManager) -> Self {
+        self.risk_manager = Some(risk_manager);
+        self
+    }
+
+    /// The drawdown of `circuit_breaker` is synced with the ledger after every commit. The
+    /// merchants of the strategies should be wrapped by the same breaker.
+    pub fn with_circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = Some(circuit_breaker);
+        self
+    }
+
+    pub fn shutdown(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    pub fn bookkeeper(&mut self) -> &mut Bookkeeper {
+        &mut self.bookkeeper
+    }
+
+    pub fn reseller(&self) -> &Reseller<'a> {
+        &self.reseller
+    }
+
+    /// Cycles until the shutdown is requested. Exchange errors are logged and the cycle
+    /// is repeated on the next tick, the other errors stop the engine.
+    pub async fn run(mut self) -> Result<(), Error> {
+        while !self.shutdown.is_requested() {
+            let started = std::time::Instant::now();
+            match self.cycle().await {
+                Ok(cycle) => log::debug!("Cycle {:#?}", cycle),
+                Err(error) if error.is_transient() => {
+                    log::warn!("Cycle failed: {}", error)
+                }
+                Err(error) => {
+                    log::error!("Engine is stopped: {}", error);
+                    self.save()?;
+                    return Err(error);
+                }
+            }
+            let rest = self.interval.checked_sub(started.elapsed()).unwrap_or_default();
+            self.wait(rest).await;
+        }
+        log::info!("Engine is shut down");
+        self.save()?;
+        self.bookkeeper.sync()
+    }
+
+    /// Check → accept → record → resell → record → requote. The fills are accepted and
+    /// saved before they reach the ledger, so a failed commit never loses an entry.
+    pub async fn cycle(&mut self) -> Result<Cycle, Error> {
+        let mut cycle = Cycle::default();
+        if self.halt(&mut cycle).await? {
+            return Ok(cycle);
+        }
+        self.record_fills(&mut cycle).await?;
+        if self.halt(&mut cycle).await? {
+            return Ok(cycle);
+        }
+        cycle.resales = match self.reseller.iterate().await {
+            Ok(resales) => resales,
+            // The entries stay in the storages and the orders are still moved.
+            Err(error) if error.is_transient() => {
+                log::warn!("Failed to resell: {}", error);
+                Vec::new()
+            }
+            Err(error) => return Err(error),
+        };
+        self.save()?;
+        let committed = self.commit(&cycle.resales, Strategy::Reseller);
+        self.sync_limits()?;
+        committed?;
+        if self.halt(&mut cycle).await? {
+            return Ok(cycle);
+        }
+        cycle.update = self.limit_master.update_orders().await?;
+        for failure in cycle.update.failures.iter() {
+            log::warn!("Failed to update orders: {}", failure);
+        }
+        self.save()?;
+        Ok(cycle)
+    }
+
+    /// Deletes my limit orders if the breaker is tripped, a failed deletion is retried by
+    /// the next cycle. The fills since the last check are recorded before the deletion.
+    async fn halt(&mut self, cycle: &mut Cycle) -> Result<bool, Error> {
+        let trip = match self.circuit_breaker.as_ref().and_then(CircuitBreaker::check) {
+            Some(trip) => trip,
+            None => {
+                self.halted = false;
+                return Ok(false);
+            }
+        };
+        if !self.halted {
+            log::error!("Engine is halted: {}", trip);
+            self.record_fills(cycle).await?;
+            self.limit_master.delete_all_my_orders().await?;
+            self.save()?;
+            self.halted = true;
+        }
+        cycle.trip = Some(trip);
+        Ok(true)
+    }
+
+    /// Checks my limit orders, accepts their fills and commits them to the ledger.
+    async fn record_fills(&mut self, cycle: &mut Cycle) -> Result<(), Error> {
+        let fills = self.limit_master.check_current_orders().await?;
+        for fill in fills.iter() {
+            self.reseller.accept_trade(fill.order.clone());
+        }
+        self.save()?;
+        let committed = self.commit(&fills, Strategy::LimitMaster);
+        cycle.fills.extend(fills);
+        self.sync_limits()?;
+        committed
+    }
+
+    /// Commits every trade to the ledger, the first failure is returned after the others
+    /// have been committed.
+    fn commit(
+        &mut self,
+        trades: &[OrderEntity<Trade>],
+        strategy: Strategy,
+    ) -> Result<(), Error> {
+        let mut failure = None;
+        for trade in trades.iter() {
+            let committed = self
+                .bookkeeper
+                .commit_trade(trade.order.clone(), trade.merchant_id, strategy);
+            if let Err(error) = committed {
+                log::error!("Failed to commit {:?}: {}", trade.order, error);
+                failure = failure.or(Some(error));
+            }
+        }
+        failure.map_or(Ok(()), Err)
+    }
+
+    fn sync_limits(&mut self) -> Result<(), Error> {
+        if let Some(risk_manager) = self.risk_manager.as_ref() {
+            risk_manager.sync(&mut self.bookkeeper)?;
+            let my_orders = self.limit_master.my_orders().map(|entity| &entity.order);
+            risk_manager.sync_orders(my_orders);
+        }
+        if let Some(circuit_breaker) = self.circuit_breaker.as_ref() {
+            circuit_breaker.sync(&mut self.bookkeeper)?;
+        }
+        Ok(())
+    }
+
+    fn save(&mut self) -> Result<(), Error> {
+        self.reseller_saver.save_storages(&self.reseller)?;
+        if let Some(limit_master_saver) = self.limit_master_saver.as_mut() {
+            limit_master_saver.save_orders(&self.limit_master)?;
+        }
+        Ok(())
+    }
+
+    /// Sleeps in short slices to notice the shutdown.
+    async fn wait(&self, duration: Duration) {
+        let deadline = std::time::Instant::now() + duration;
+        loop {
+            let now = std::time::Instant::now();
+            if self.shutdown.is_requested() || now >= deadline {
+                return;
+            }
+            futures_timer::Delay::new(Self::SHUTDOWN_POLL.min(deadline - now)).await;
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use crate::instrument::Violation;
+use crate::limit_master::MerchantId;
+use crate::risk::Rejection;
+use agnostic::trading_pair::TradingPair;
+
+/// Errors of the exchange calls carry the merchant and the trading pair they happened on, so
+/// the caller can decide whether to retry without parsing the message.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    Sniffer {
+        merchant_id: MerchantId,
+        trading_pair: TradingPair,
+        source: String,
+    },
+    Trader {
+        merchant_id: MerchantId,
+        trading_pair: TradingPair,
+        source: String,
+    },
+    Accountant {
+        merchant_id: MerchantId,
+        trading_pair: TradingPair,
+        source: String,
+    },
+    /// The order breaks the precision rules of the merchant, it is never sent.
+    Instrument {
+        merchant_id: MerchantId,
+        trading_pair: TradingPair,
+        violation: Violation,
+    },
+    /// The order breaks the limits of the `RiskManager`, it is never sent.
+    Risk {
+        merchant_id: MerchantId,
+        trading_pair: TradingPair,
+        rejection: Rejection,
+    },
+    Persistence {
+        merchant_id: Option<MerchantId>,
+        trading_pair: Option<TradingPair>,
+        source: String,
+    },
+    Configuration {
+        merchant_id: Option<MerchantId>,
+        trading_pair: Option<TradingPair>,
+        source: String,
+    },
+}
+
+impl Error {
+    pub fn sniffer(merchant_id: MerchantId, trading_pair: TradingPair, source: String) -> Self {
+        Error::Sniffer {
+            merchant_id,
+            trading_pair,
+            source,
+        }
+    }
+
+    pub fn trader(merchant_id: MerchantId, trading_pair: TradingPair, source: String) -> Self {
+        Error::Trader {
+            merchant_id,
+            trading_pair,
+            source,
+        }
+    }
+
+    pub fn accountant(
+        merchant_id: MerchantId,
+        trading_pair: TradingPair,
+        source: String,
+    ) -> Self {
+        Error::Accountant {
+            merchant_id,
+            trading_pair,
+            source,
+        }
+    }
+
+    pub fn instrument(
+        merchant_id: MerchantId,
+        trading_pair: TradingPair,
+        violation: Violation,
+    ) -> Self {
+        Error::Instrument {
+            merchant_id,
+            trading_pair,
+            violation,
+        }
+    }
+
+    pub fn risk(
+        merchant_id: MerchantId,
+        trading_pair: TradingPair,
+        rejection: Rejection,
+    ) -> Self {
+        Error::Risk {
+            merchant_id,
+            trading_pair,
+            rejection,
+        }
+    }
+
+    pub fn persistence(source: impl Into<String>) -> Self {
+        Error::Persistence {
+            merchant_id: None,
+            trading_pair: None,
+            source: source.into(),
+        }
+    }
+
+    pub fn configuration(source: impl Into<String>) -> Self {
+        Error::Configuration {
+            merchant_id: None,
+            trading_pair: None,
+            source: source.into(),
+        }
+    }
+
+    /// Exchange errors are worth to retry on the next iteration, the others are not. The
+    /// risk limits may let the order through later, so their rejections are retried too.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Sniffer { .. }
+            | Error::Trader { .. }
+            | Error::Accountant { .. }
+            | Error::Risk { .. } => true,
+            Error::Instrument { .. }
+            | Error::Persistence { .. }
+            | Error::Configuration { .. } => false,
+        }
+    }
+
+    pub fn merchant_id(&self) -> Option<MerchantId> {
+        match self {
+            Error::Sniffer { merchant_id, .. }
+            | Error::Trader { merchant_id, .. }
+            | Error::Accountant { merchant_id, .. }
+            | Error::Instrument { merchant_id, .. }
+            | Error::Risk { merchant_id, .. } => Some(*merchant_id),
+            Error::Persistence { merchant_id, .. }
+            | Error::Configuration { merchant_id, .. } => *merchant_id,
+        }
+    }
+
+    pub fn trading_pair(&self) -> Option<&TradingPair> {
+        match self {
+            Error::Sniffer { trading_pair, .. }
+            | Error::Trader { trading_pair, .. }
+            | Error::Accountant { trading_pair, .. }
+            | Error::Instrument { trading_pair, .. }
+            | Error::Risk { trading_pair, .. } => Some(trading_pair),
+            Error::Persistence { trading_pair, .. }
+            | Error::Configuration { trading_pair, .. } => trading_pair.as_ref(),
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Error::Sniffer { source, .. }
+            | Error::Trader { source, .. }
+            | Error::Accountant { source, .. }
+            | Error::Persistence { source, .. }
+            | Error::Configuration { source, .. } => source.clone(),
+            Error::Instrument { violation, .. } => violation.to_string(),
+            Error::Risk { rejection, .. } => rejection.to_string(),
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Error::Sniffer { .. } => "Sniffer",
+            Error::Trader { .. } => "Trader",
+            Error::Accountant { .. } => "Accountant",
+            Error::Instrument { .. } => "Instrument",
+            Error::Risk { .. } => "Risk",
+            Error::Persistence { .. } => "Persistence",
+            Error::Configuration { .. } => "Configuration",
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} error", self.kind())?;
+        if let Some(merchant_id) = self.merchant_id() {
+            write!(f, " on {}", merchant_id)?;
+        }
+        if let Some(trading_pair) = self.trading_pair() {
+            write!(f, " ({:?})", trading_pair)?;
+        }
+        write!(f, ": {}", self.message())
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Persistence {
+            merchant_id: None,
+            trading_pair: None,
+            source: error.to_string(),
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::Persistence {
+            merchant_id: None,
+            trading_pair: None,
+            source: error.to_string(),
+        }
+    }
+}
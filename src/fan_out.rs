@@ -0,0 +1,81 @@
+//! Fan Out
+//!
+//! Queries several merchants at once. The results are returned in the order of the requests,
+//! so the callers behave exactly like the sequential loops they replace.
+use futures::future::Either;
+use futures::{Future, StreamExt};
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug)]
+pub struct FanOut {
+    /// Time limit of a single exchange call.
+    pub timeout: Duration,
+    /// Max number of the exchange calls in flight.
+    pub concurrency: usize,
+}
+
+impl Default for FanOut {
+    fn default() -> Self {
+        FanOut {
+            timeout: Duration::from_secs(10),
+            concurrency: 16,
+        }
+    }
+}
+
+impl FanOut {
+    pub async fn join<TFuture: Future>(
+        &self,
+        futures: impl IntoIterator<Item = TFuture>,
+    ) -> Vec<TFuture::Output> {
+        futures::stream::iter(futures)
+            .buffered(self.concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    pub async fn call<T>(
+        &self,
+        future: impl Future<Output = Result<T, String>>,
+    ) -> Result<T, String> {
+        let delay = futures_timer::Delay::new(self.timeout);
+        match futures::future::select(Box::pin(future), delay).await {
+            Either::Left((result, _delay)) => result,
+            Either::Right(((), _future)) => Err(format!("Timed out after {:?}", self.timeout)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn join_keeps_order() {
+        let fan_out = FanOut {
+            timeout: Duration::from_millis(100),
+            concurrency: 2,
+        };
+        let delays = vec![30u64, 10, 20];
+        let result = tokio_test::block_on(fan_out.join(delays.iter().map(|delay| async move {
+            futures_timer::Delay::new(Duration::from_millis(*delay)).await;
+            *delay
+        })));
+        assert_eq!(result, delays);
+    }
+
+    #[test]
+    fn call_timeout() {
+        let fan_out = FanOut {
+            timeout: Duration::from_millis(10),
+            concurrency: 1,
+        };
+        let result = tokio_test::block_on(fan_out.call(async {
+            futures_timer::Delay::new(Duration::from_millis(1000)).await;
+            Ok(())
+        }));
+        assert!(result.is_err());
+        let result = tokio_test::block_on(fan_out.call(async { Ok(1) }));
+        assert_eq!(result, Ok(1));
+    }
+}
@@ -0,0 +1,204 @@
+//! Instrument
+//!
+//! Precision rules of the merchants. Prices are rounded to the tick in favour of the
+//! order's side, amounts are rounded down to the lot step, so an order never spends more
+//! than it was calculated for.
+use crate::decimal::{self, Decimal};
+use crate::error::Error;
+use crate::limit_master::MerchantId;
+use agnostic::order::Order;
+use agnostic::trading_pair::{Coins, Side};
+use std::collections::HashMap;
+
+/// Zero disables the corresponding rule.
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub struct InstrumentSpec {
+    pub tick_size: Decimal,
+    pub lot_step: Decimal,
+    pub min_amount: Decimal,
+    /// Min price * amount of an order.
+    pub min_notional: Decimal,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Violation {
+    PriceBelowTick { price: Decimal, tick_size: Decimal },
+    AmountBelowMinimum { amount: Decimal, min_amount: Decimal },
+    NotionalBelowMinimum { notional: Decimal, min_notional: Decimal },
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Violation::PriceBelowTick { price, tick_size } => {
+                write!(f, "Price {} is below the tick size {}", price, tick_size)
+            }
+            Violation::AmountBelowMinimum { amount, min_amount } => {
+                write!(f, "Amount {} is below the minimum {}", amount, min_amount)
+            }
+            Violation::NotionalBelowMinimum {
+                notional,
+                min_notional,
+            } => write!(f, "Notional {} is below the minimum {}", notional, min_notional),
+        }
+    }
+}
+
+impl InstrumentSpec {
+    /// Buy prices are rounded down and sell prices up.
+    pub fn normalize(&self, order: Order) -> Result<Order, Violation> {
+        let raw_price = decimal::from_f64(order.price);
+        let price = match order.trading_pair.side {
+            Side::Buy => round_down(raw_price, self.tick_size),
+            Side::Sell => round_up(raw_price, self.tick_size),
+        };
+        if price <= Decimal::ZERO {
+            return Err(Violation::PriceBelowTick {
+                price: raw_price,
+                tick_size: self.tick_size,
+            });
+        }
+        let amount = round_down(decimal::from_f64(order.amount), self.lot_step);
+        if amount < self.min_amount || amount <= Decimal::ZERO {
+            return Err(Violation::AmountBelowMinimum {
+                amount,
+                min_amount: self.min_amount,
+            });
+        }
+        let notional = price * amount;
+        if notional < self.min_notional {
+            return Err(Violation::NotionalBelowMinimum {
+                notional,
+                min_notional: self.min_notional,
+            });
+        }
+        Ok(Order {
+            price: decimal::to_f64(price),
+            amount: decimal::to_f64(amount),
+            ..order
+        })
+    }
+}
+
+/// Specs of every merchant and coins. The orders without a spec are sent as they are.
+#[derive(Default, Clone, Debug)]
+pub struct Instruments {
+    specs: HashMap<(String, Coins), InstrumentSpec>,
+}
+
+impl Instruments {
+    pub fn with_spec(
+        mut self,
+        merchant_id: &str,
+        coins: Coins,
+        spec: InstrumentSpec,
+    ) -> Self {
+        self.specs.insert((merchant_id.to_owned(), coins), spec);
+        self
+    }
+
+    pub fn spec(&self, merchant_id: &str, coins: Coins) -> Option<&InstrumentSpec> {
+        self.specs.get(&(merchant_id.to_owned(), coins))
+    }
+
+    pub fn normalize(&self, merchant_id: MerchantId, order: Order) -> Result<Order, Error> {
+        match self.spec(merchant_id, order.trading_pair.coins) {
+            Some(spec) => {
+                let trading_pair = order.trading_pair.clone();
+                spec.normalize(order).map_err(|violation| {
+                    Error::instrument(merchant_id, trading_pair, violation)
+                })
+            }
+            None => Ok(order),
+        }
+    }
+}
+
+fn round_down(value: Decimal, step: Decimal) -> Decimal {
+    if step <= Decimal::ZERO {
+        return value;
+    }
+    (value / step).floor() * step
+}
+
+fn round_up(value: Decimal, step: Decimal) -> Decimal {
+    if step <= Decimal::ZERO {
+        return value;
+    }
+    (value / step).ceil() * step
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use agnostic::trading_pair::{Target, TradingPair};
+    use rust_decimal_macros::dec;
+
+    fn order(side: Side, price: f64, amount: f64) -> Order {
+        Order {
+            trading_pair: TradingPair {
+                coins: Coins::TonUsdt,
+                side,
+                target: Target::Limit,
+            },
+            price,
+            amount,
+        }
+    }
+
+    #[test]
+    fn normalize() {
+        let spec = InstrumentSpec {
+            tick_size: dec!(0.01),
+            lot_step: dec!(0.1),
+            min_amount: dec!(1),
+            min_notional: dec!(5),
+        };
+        let normalized = spec.normalize(order(Side::Buy, 1.2345, 10.37)).unwrap();
+        assert_eq!(normalized.price, 1.23);
+        assert_eq!(normalized.amount, 10.3);
+        let normalized = spec.normalize(order(Side::Sell, 1.2345, 10.0)).unwrap();
+        assert_eq!(normalized.price, 1.24);
+        assert_eq!(normalized.amount, 10.0);
+        assert_eq!(
+            spec.normalize(order(Side::Buy, 1.0, 0.95)),
+            Err(Violation::AmountBelowMinimum {
+                amount: dec!(0.9),
+                min_amount: dec!(1)
+            })
+        );
+        assert!(matches!(
+            spec.normalize(order(Side::Buy, 1.0, 4.0)),
+            Err(Violation::NotionalBelowMinimum { .. })
+        ));
+        assert!(matches!(
+            spec.normalize(order(Side::Buy, 0.001, 4000.0)),
+            Err(Violation::PriceBelowTick { .. })
+        ));
+    }
+
+    #[test]
+    fn instruments() {
+        let instruments = Instruments::default().with_spec(
+            "Test",
+            Coins::TonUsdt,
+            InstrumentSpec {
+                tick_size: dec!(0.1),
+                ..InstrumentSpec::default()
+            },
+        );
+        let normalized = instruments
+            .normalize("Test", order(Side::Buy, 1.25, 1.0))
+            .unwrap();
+        assert_eq!(normalized.price, 1.2);
+        let untouched = instruments
+            .normalize("Other", order(Side::Buy, 1.25, 1.0))
+            .unwrap();
+        assert_eq!(untouched.price, 1.25);
+        let error = instruments
+            .normalize("Test", order(Side::Buy, 0.01, 1.0))
+            .unwrap_err();
+        assert!(!error.is_transient());
+        assert_eq!(error.merchant_id(), Some("Test"));
+    }
+}
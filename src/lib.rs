@@ -1,8 +1,21 @@
 pub mod best_price_trader;
+pub mod arbitrage;
 pub mod limit_master;
 pub mod bookkeeper;
 pub mod reseller;
 pub mod reseller_saver;
+pub mod limit_master_saver;
+mod atomic_file;
 pub mod calculators;
 pub mod filters;
 pub mod deleter;
+pub mod error;
+pub mod fan_out;
+pub mod instrument;
+pub mod risk;
+pub mod circuit_breaker;
+pub mod decimal;
+pub mod engine;
+pub mod recorder;
+pub mod backtest;
+pub mod paper;
@@ -1,40 +1,68 @@
 //! Limit Master
 //!
 //! There are two main stages: check current limit orders state & update current limit orders
-//! state.
+//! state. The update diffs the desired orders against my last known ones, only the orders
+//! which moved beyond the `Tolerance` are cancelled and placed again.
 //!
-//! TODO: Allow Limit Master to load last OrdersStorage. It will be requiered for deserialization.
-//! Now it is requiered for testing check method wihtout calling an update.
+//! The last known state of my orders can be saved with `LimitMasterSaver` and restored with
+//! `LimitMaster::resume`, so the fills which happened while we were down are reported by the
+//! first check after a restart.
+//!
+//! Every merchant gets a ladder of limit orders per side, a single order by default. The
+//! rungs share the balance of the merchant and are tracked one by one.
 use crate::calculators::amount_calculator::Balance;
+use crate::calculators::fee_model::FeeModel;
+use crate::calculators::inventory_skew::InventorySkew;
+use crate::calculators::ladder::Ladder;
 use crate::calculators::price_calculator::PriceCalculator;
+use crate::calculators::spread_model::SpreadModel;
 use crate::calculators::AmountCalculator;
+use crate::decimal::{self, Decimal};
 use crate::deleter::Deleter;
+use crate::error::Error;
+use crate::fan_out::FanOut;
+use crate::instrument::Instruments;
+use crate::limit_master_saver::{OrderSnapshot, OrdersSnapshot};
+use crate::risk::RiskManager;
 use agnostic::merchant::Merchant;
 use agnostic::order::{Order, OrderWithId};
 use agnostic::trade::Trade;
 use agnostic::trading_pair::{Coins, Side, Target, TradingPair};
+use std::convert::TryFrom;
 
 pub type MerchantId = &'static str;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct OrderEntity<TOrder> {
     pub merchant_id: MerchantId,
     pub order: TOrder,
+    /// Rung of the ladder the order belongs to, zero for the orders out of a ladder.
+    pub level: usize,
 }
 
 pub fn entity_to_string(entity: OrderEntity<OrderWithId>) -> String {
     format!(
-        "{:8} {:10} {:10} price {:11.5} amount {:11.5}",
+        "{:8} {:10} {:10} level {:2} price {:11.5} amount {:11.5}",
         entity.merchant_id,
         entity.order.trading_pair.side,
         entity.order.trading_pair.target,
+        entity.level,
         entity.order.price,
         entity.order.amount)
 }
 
 impl<TOrder> OrderEntity<TOrder> {
     pub fn new(merchant_id: MerchantId, order: TOrder) -> Self {
-        OrderEntity { merchant_id, order }
+        OrderEntity {
+            merchant_id,
+            order,
+            level: 0,
+        }
+    }
+
+    pub fn with_level(mut self, level: usize) -> Self {
+        self.level = level;
+        self
     }
 }
 
@@ -61,6 +89,62 @@ impl<TOrder> OrdersStorage<TOrder> {
     }
 }
 
+impl OrdersStorage<OrderWithId> {
+    pub fn snapshot(&self) -> OrdersSnapshot {
+        let to_snapshot = |entity: &OrderEntity<OrderWithId>| OrderSnapshot {
+            merchant_id: entity.merchant_id.to_owned(),
+            id: entity.order.id.clone(),
+            side: entity.order.trading_pair.side.into(),
+            price: decimal::from_f64(entity.order.price),
+            amount: decimal::from_f64(entity.order.amount),
+            level: entity.level,
+        };
+        OrdersSnapshot {
+            coins: self.coins.into(),
+            sell_stock: self.sell_stock.iter().map(to_snapshot).collect(),
+            buy_stock: self.buy_stock.iter().map(to_snapshot).collect(),
+        }
+    }
+
+    /// Orders of merchants which are not managed by `merchants_manager` are dropped.
+    pub fn from_snapshot(
+        snapshot: &OrdersSnapshot,
+        merchants_manager: &MerchantIdManager,
+    ) -> Result<Self, Error> {
+        let coins = Coins::try_from(snapshot.coins.clone())?;
+        let to_entity = |order: &OrderSnapshot| {
+            let merchant = match merchants_manager.get_merchant(&order.merchant_id) {
+                Some(merchant) => merchant,
+                None => {
+                    log::warn!(
+                        "Unknown merchant {} of the order {}",
+                        order.merchant_id,
+                        order.id);
+                    return None;
+                }
+            };
+            Some(OrderEntity::new(
+                merchant.id(),
+                OrderWithId {
+                    id: order.id.clone(),
+                    trading_pair: TradingPair {
+                        coins,
+                        side: order.side.clone().into(),
+                        target: Target::Limit,
+                    },
+                    price: decimal::to_f64(order.price),
+                    amount: decimal::to_f64(order.amount),
+                },
+            ).with_level(order.level))
+        };
+        Ok(OrdersStorage {
+            coins,
+            sell_stock: snapshot.sell_stock.iter().filter_map(to_entity).collect(),
+            buy_stock: snapshot.buy_stock.iter().filter_map(to_entity).collect(),
+        })
+    }
+}
+
 pub struct MerchantIdManager<'a> {
     merchants: &'a [&'a dyn Merchant],
 }
@@ -77,7 +161,7 @@ impl<'a> MerchantIdManager<'a> {
             .map(|merchant| merchant.id())
     }
 
-    pub fn get_merchant(&self, id: MerchantId) -> Option<&dyn Merchant> {
+    pub fn get_merchant(&self, id: &str) -> Option<&dyn Merchant> {
         self.merchants.iter()
             .find(|merchant| merchant.id() == id)
             .map(|m| *m)
@@ -86,20 +170,81 @@ impl<'a> MerchantIdManager<'a> {
     pub fn iter(&self) -> std::slice::Iter<'_, &dyn Merchant> {
         self.merchants.iter()
     }
+
+    pub fn merchants(&self) -> &'a [&'a dyn Merchant] {
+        self.merchants
+    }
+}
+
+/// Relative differences of price and amount which do not require an order to be replaced.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Tolerance {
+    pub price: Decimal,
+    pub amount: Decimal,
+}
+
+impl Tolerance {
+    pub fn accepts(&self, order: &OrderWithId, price: Decimal, amount: Decimal) -> bool {
+        relative_difference(decimal::from_f64(order.price), price) <= self.price
+            && relative_difference(decimal::from_f64(order.amount), amount) <= self.amount
+    }
+}
+
+/// What `order` holds of the coin which is spent on `side`: the buy orders hold the
+/// quote coin and the sell orders hold the base one.
+fn locked_amount(side: Side, order: &OrderWithId) -> Decimal {
+    let amount = decimal::from_f64(order.amount);
+    match side {
+        Side::Buy => decimal::from_f64(order.price) * amount,
+        Side::Sell => amount,
+    }
+}
+
+fn relative_difference(current: Decimal, desired: Decimal) -> Decimal {
+    if current == desired {
+        Decimal::ZERO
+    } else if desired.is_zero() {
+        Decimal::MAX
+    } else {
+        (current - desired).abs() / desired.abs()
+    }
 }
 
 #[derive(Clone, Debug)]
+pub enum OrderChange {
+    Kept(OrderEntity<OrderWithId>),
+    /// The trader has no call to amend an order, so a moved order is cancelled and
+    /// placed again.
+    Replaced {
+        old: OrderEntity<OrderWithId>,
+        new: OrderEntity<OrderWithId>,
+    },
+    Created(OrderEntity<OrderWithId>),
+    Cancelled(OrderEntity<OrderWithId>),
+}
+
+#[derive(Clone, Debug, Default)]
 pub struct Update {
     pub sell: Vec<OrderEntity<OrderWithId>>,
     pub buy: Vec<OrderEntity<OrderWithId>>,
+    pub changes: Vec<OrderChange>,
+    /// Merchants which failed during the update, the other merchants are updated anyway.
+    pub failures: Vec<Error>,
 }
 
 pub struct LimitMaster<'a> {
     coins: Coins,
     merchants_manager: MerchantIdManager<'a>,
     my_orders_last_state: OrdersStorage<OrderWithId>,
-    price_calculator: PriceCalculator,
+    spread_model: Box<dyn SpreadModel>,
     amount_calculator: AmountCalculator,
+    tolerance: Tolerance,
+    fan_out: FanOut,
+    fee_model: FeeModel,
+    instruments: Instruments,
+    risk_manager: RiskManager,
+    ladder: Ladder,
+    inventory_skew: Option<InventorySkew>,
 }
 
 impl<'a> LimitMaster<'a> {
@@ -112,8 +257,15 @@ impl<'a> LimitMaster<'a> {
         LimitMaster {
             coins: coins.clone(),
             merchants_manager,
-            price_calculator,
+            spread_model: Box::new(price_calculator),
             amount_calculator,
+            tolerance: Tolerance::default(),
+            fan_out: FanOut::default(),
+            fee_model: FeeModel::default(),
+            instruments: Instruments::default(),
+            risk_manager: RiskManager::default(),
+            ladder: Ladder::default(),
+            inventory_skew: None,
             my_orders_last_state: OrdersStorage {
                 coins,
                 sell_stock: Vec::with_capacity(16),
@@ -122,11 +274,97 @@ impl<'a> LimitMaster<'a> {
         }
     }
 
-    pub async fn check_current_orders(&mut self) -> Result<Vec<Trade>, String> {
-        let my_current_orders = self.accumulate_my_current_order().await;
+    /// Creates a `LimitMaster` which continues to track the orders from `snapshot`.
+    pub fn resume(
+        coins: Coins,
+        merchants_manager: MerchantIdManager<'a>,
+        price_calculator: PriceCalculator,
+        amount_calculator: AmountCalculator,
+        snapshot: &OrdersSnapshot,
+    ) -> Self {
+        let mut limit_master =
+            Self::new(coins, merchants_manager, price_calculator, amount_calculator);
+        match OrdersStorage::from_snapshot(snapshot, &limit_master.merchants_manager) {
+            Ok(last_state) if last_state.coins == coins => {
+                limit_master.my_orders_last_state = last_state
+            }
+            _ => log::warn!("Snapshot of {} is ignored by {:?}", snapshot.coins, coins),
+        }
+        limit_master
+    }
+
+    pub fn with_tolerance(mut self, tolerance: Tolerance) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn with_fan_out(mut self, fan_out: FanOut) -> Self {
+        self.fan_out = fan_out;
+        self
+    }
+
+    /// The profit of the spread model is net of the fees of the model, zero by default.
+    pub fn with_fee_model(mut self, fee_model: FeeModel) -> Self {
+        self.fee_model = fee_model;
+        self
+    }
+
+    /// Replaces the fixed profit of the `PriceCalculator` given on creation.
+    pub fn with_spread_model(mut self, spread_model: Box<dyn SpreadModel>) -> Self {
+        self.spread_model = spread_model;
+        self
+    }
+
+    /// The limit orders are rounded to the specs of the merchants before they are placed.
+    pub fn with_instruments(mut self, instruments: Instruments) -> Self {
+        self.instruments = instruments;
+        self
+    }
+
+    /// Every limit order is checked against the current best order of the market.
+    pub fn with_risk_manager(mut self, risk_manager: RiskManager) -> Self {
+        self.risk_manager = risk_manager;
+        self
+    }
+
+    /// The amount of a single order is split between the rungs of `ladder`.
+    pub fn with_ladder(mut self, ladder: Ladder) -> Self {
+        self.ladder = ladder;
+        self
+    }
+
+    /// The quotes are shifted by the base coin inventory of all the merchants.
+    pub fn with_inventory_skew(mut self, inventory_skew: InventorySkew) -> Self {
+        self.inventory_skew = Some(inventory_skew);
+        self
+    }
+
+    pub fn snapshot(&self) -> OrdersSnapshot {
+        self.my_orders_last_state.snapshot()
+    }
+
+    /// My limit orders by the last check or update.
+    pub fn my_orders(&self) -> impl Iterator<Item = &OrderEntity<OrderWithId>> {
+        let state = &self.my_orders_last_state;
+        state.buy_stock.iter().chain(state.sell_stock.iter())
+    }
+
+    /// Returns the fills of my orders since the last check.
+    pub async fn check_current_orders(&mut self) -> Result<Vec<OrderEntity<Trade>>, Error> {
+        let (my_current_orders, failures) = self.accumulate_my_current_order().await;
         log::debug!("My current orders {:#?}", my_current_orders);
-        log::debug!("Last state {:#?}", my_current_orders);
-        let performed_trades = self.my_orders_last_state.buy_stock.iter_mut().fold(
+        log::debug!("Last state {:#?}", self.my_orders_last_state);
+        // Orders of the merchants which failed to answer are checked on the next call.
+        let failed_merchants: Vec<_> = failures
+            .iter()
+            .inspect(|error| log::warn!("Failed to check orders: {}", error))
+            .filter_map(Error::merchant_id)
+            .collect();
+        let is_available = |last_order: &&mut OrderEntity<OrderWithId>| {
+            !failed_merchants.contains(&last_order.merchant_id)
+        };
+        let buy_stock = self.my_orders_last_state.buy_stock.iter_mut().filter(is_available);
+        let performed_trades = buy_stock.fold(
             Vec::with_capacity(16),
             |mut acc, last_order| {
                 my_current_orders
@@ -153,12 +391,14 @@ impl<'a> LimitMaster<'a> {
                     )
                     .and_then(|trade| {
                         last_order.order.amount -= trade.amount();
-                        Some(acc.push(trade))
+                        let entity = OrderEntity::new(last_order.merchant_id, trade);
+                        Some(acc.push(entity.with_level(last_order.level)))
                     });
                 acc
             },
         );
-        Ok(self.my_orders_last_state.sell_stock.iter_mut().fold(
+        let sell_stock = self.my_orders_last_state.sell_stock.iter_mut().filter(is_available);
+        Ok(sell_stock.fold(
             performed_trades,
             |mut acc, last_order| {
                 my_current_orders
@@ -182,127 +422,465 @@ impl<'a> LimitMaster<'a> {
                     )
                     .and_then(|trade| {
                         last_order.order.amount -= trade.amount();
-                        Some(acc.push(trade))
+                        let entity = OrderEntity::new(last_order.merchant_id, trade);
+                        Some(acc.push(entity.with_level(last_order.level)))
                     });
                 acc
             },
         ))
     }
 
-    pub async fn update_orders(&mut self) -> Result<Update, String> {
-        self.delete_all_my_orders().await?;
-        let current_orders_storage = self.accumulate_merchants_infomration().await;
+    /// Moves my orders towards the desired state. Orders which are within the tolerance of
+    /// the desired price and amount are kept on the exchange, the others are replaced.
+    pub async fn update_orders(&mut self) -> Result<Update, Error> {
+        let (current_orders_storage, mut failures) =
+            self.accumulate_merchants_infomration().await;
+        self.observe_mid_price(&current_orders_storage);
+        let mut changes = Vec::with_capacity(16);
+        // Quoting without the inventory may break the position limits.
+        let inventory = match self.inventory().await {
+            Ok(inventory) => inventory,
+            Err(error) => {
+                failures.push(error);
+                return Ok(Update {
+                    buy: Vec::new(),
+                    sell: Vec::new(),
+                    changes,
+                    failures,
+                });
+            }
+        };
         let buy = self
-            .update_orders_on_side(Side::Buy, &current_orders_storage)
-            .await?;
+            .update_orders_on_side(
+                Side::Buy,
+                &current_orders_storage,
+                inventory,
+                &mut changes,
+                &mut failures,
+            )
+            .await;
         let sell = self
-            .update_orders_on_side(Side::Sell, &current_orders_storage)
-            .await?;
-        Ok(Update { buy, sell })
+            .update_orders_on_side(
+                Side::Sell,
+                &current_orders_storage,
+                inventory,
+                &mut changes,
+                &mut failures,
+            )
+            .await;
+        Ok(Update { buy, sell, changes, failures })
     }
 
     async fn update_orders_on_side(
         &mut self,
         side: Side,
         current_orders_storage: &OrdersStorage<Order>,
-    ) -> Result<Vec<OrderEntity<OrderWithId>>, String> {
-        let coins = self.coins.clone();
+        inventory: Option<Decimal>,
+        changes: &mut Vec<OrderChange>,
+        failures: &mut Vec<Error>,
+    ) -> Vec<OrderEntity<OrderWithId>> {
+        let target = self.limit_order_target(side, current_orders_storage);
+        let min_amount = self.amount_calculator.min_amount_threshold;
+        // Orders which are filled by check_current_orders are not on the exchange anymore.
+        self.my_stock_mut(side)
+            .retain(|entity| decimal::from_f64(entity.order.amount) > min_amount);
+        let mut orders = Vec::with_capacity(10);
+        for merchant in self.merchants_manager.merchants().iter() {
+            let existing: Vec<_> = self
+                .my_stock(side)
+                .iter()
+                .filter(|entity| entity.merchant_id == merchant.id())
+                .cloned()
+                .collect();
+            let desired = match target {
+                Some((market_price, best_amount)) => {
+                    let locked = existing
+                        .iter()
+                        .map(|entity| locked_amount(side, &entity.order))
+                        .sum();
+                    let balance = match self.balance(*merchant, side, locked).await {
+                        Ok(balance) => balance,
+                        Err(error) => {
+                            failures.push(error);
+                            continue;
+                        }
+                    };
+                    match self.amount_calculator.evaluate(best_amount, &balance) {
+                        Some(amount) => self.rungs(
+                            merchant.id(),
+                            side,
+                            market_price,
+                            amount.value(),
+                            inventory,
+                        ),
+                        None => Vec::new(),
+                    }
+                }
+                None => Vec::new(),
+            };
+            // The orders on the exchange are rounded, so are the rungs matched to them.
+            let desired: Vec<_> = desired
+                .into_iter()
+                .filter_map(|(level, price, amount)| {
+                    match self.limit_order(merchant.id(), side, price, amount) {
+                        Ok(order) => Some((level, order)),
+                        Err(error) => {
+                            failures.push(error);
+                            None
+                        }
+                    }
+                })
+                .collect();
+            let mut kept: Vec<OrderEntity<OrderWithId>> = Vec::with_capacity(desired.len());
+            let mut obsolete = Vec::with_capacity(existing.len());
+            for entity in existing.into_iter() {
+                let is_kept = desired.iter().any(|(level, order)| {
+                    entity.level == *level
+                        && kept.iter().all(|kept| kept.level != *level)
+                        && self.tolerance.accepts(
+                            &entity.order,
+                            decimal::from_f64(order.price),
+                            decimal::from_f64(order.amount),
+                        )
+                });
+                if is_kept {
+                    kept.push(entity)
+                } else {
+                    obsolete.push(entity)
+                }
+            }
+            let obsolete_count = obsolete.len();
+            let mut cancelled = Vec::with_capacity(obsolete_count);
+            for entity in obsolete.into_iter() {
+                match self.cancel_order(*merchant, &entity).await {
+                    Ok(()) => cancelled.push(entity),
+                    Err(error) => failures.push(error),
+                }
+            }
+            if cancelled.len() < obsolete_count {
+                // Placing a new order next to the one we failed to cancel may exceed the
+                // balance, so the merchant is left as is until the next update.
+                changes.extend(cancelled.into_iter().map(OrderChange::Cancelled));
+                changes.extend(kept.iter().cloned().map(OrderChange::Kept));
+                orders.extend(kept);
+                continue;
+            }
+            let mut cancelled = cancelled.into_iter();
+            for (level, limit_order) in desired.into_iter() {
+                if let Some(entity) = kept.iter().find(|entity| entity.level == level) {
+                    changes.push(OrderChange::Kept(entity.clone()));
+                    orders.push(entity.clone());
+                    continue;
+                }
+                let best_price = target.map(|(market_price, _amount)| market_price);
+                match self
+                    .create_limit_order(*merchant, level, limit_order, best_price)
+                    .await
+                {
+                    Ok(entity) => {
+                        changes.push(match cancelled.next() {
+                            Some(old) => OrderChange::Replaced { old, new: entity.clone() },
+                            None => OrderChange::Created(entity.clone()),
+                        });
+                        orders.push(entity);
+                    }
+                    Err(error) => failures.push(error),
+                }
+            }
+            changes.extend(cancelled.map(OrderChange::Cancelled));
+        }
+        orders
+    }
+
+    /// Returns the price and the amount of the best stock order for a limit order on `side`.
+    /// The price of the limit order itself depends on the fees of the merchant.
+    fn limit_order_target(
+        &self,
+        side: Side,
+        current_orders_storage: &OrdersStorage<Order>,
+    ) -> Option<(Decimal, Decimal)> {
         let min_amount = self.amount_calculator.min_amount_threshold;
-        let market_stock: Vec<_> = current_orders_storage
+        // A broken price of a single merchant must not stop the quoting on the others.
+        let market_stock = current_orders_storage
             .get_stock(Target::Market, side)
             .iter()
-            .filter(|entity| entity.order.amount > min_amount)
-            .collect();
+            .filter(|entity| entity.order.price.is_finite() && entity.order.price > 0.0)
+            .filter(|entity| decimal::from_f64(entity.order.amount) > min_amount);
+        let by_price = |left: &&OrderEntity<Order>, right: &&OrderEntity<Order>| {
+            left.order.price.total_cmp(&right.order.price)
+        };
         let best_stock_order = match side {
-            Side::Buy => market_stock.iter().min_by(|left, right| left.order.price.partial_cmp(&right.order.price).unwrap()),
-            Side::Sell => market_stock.iter().max_by(|left, right| left.order.price.partial_cmp(&right.order.price).unwrap()),
+            Side::Buy => market_stock.min_by(by_price),
+            Side::Sell => market_stock.max_by(by_price),
+        }?;
+        Some((
+            decimal::from_f64(best_stock_order.order.price),
+            decimal::from_f64(best_stock_order.order.amount),
+        ))
+    }
+
+    fn observe_mid_price(&mut self, current_orders_storage: &OrdersStorage<Order>) {
+        let ask = self.limit_order_target(Side::Buy, current_orders_storage);
+        let bid = self.limit_order_target(Side::Sell, current_orders_storage);
+        if let (Some((ask, _)), Some((bid, _))) = (ask, bid) {
+            self.spread_model.observe((ask + bid) / Decimal::from(2));
+        }
+    }
+
+    fn limit_price(
+        &self,
+        merchant_id: &str,
+        side: Side,
+        market_price: Decimal,
+    ) -> Decimal {
+        let fees = self.fee_model.fees(merchant_id);
+        let price_calculator = PriceCalculator {
+            profit: self.spread_model.profit(),
+        };
+        match side {
+            Side::Buy => price_calculator.low_with_fees(market_price, fees),
+            Side::Sell => price_calculator.high_with_fees(market_price, fees),
+        }
+    }
+
+    /// Levels, prices and amounts of the rungs which split `amount` on `side`. The rungs
+    /// below the min amount are skipped.
+    fn rungs(
+        &self,
+        merchant_id: &str,
+        side: Side,
+        market_price: Decimal,
+        amount: Decimal,
+        inventory: Option<Decimal>,
+    ) -> Vec<(usize, Decimal, Decimal)> {
+        let price = self.limit_price(merchant_id, side, market_price);
+        let (price, amount) = match (&self.inventory_skew, inventory) {
+            (Some(skew), Some(inventory)) => (
+                skew.price(price, inventory),
+                skew.amount(side, amount, inventory),
+            ),
+            _ => (price, amount),
+        };
+        let min_amount = self.amount_calculator.min_amount_threshold;
+        self.ladder
+            .rungs
+            .iter()
+            .enumerate()
+            .map(|(level, rung)| (level, rung.price(side, price), rung.amount(amount)))
+            .filter(|(_level, _price, amount)| *amount >= min_amount)
+            .collect()
+    }
+
+    /// Base coin of all the merchants including my sell orders, `None` without the
+    /// inventory skew.
+    async fn inventory(&self) -> Result<Option<Decimal>, Error> {
+        if self.inventory_skew.is_none() {
+            return Ok(None);
+        }
+        let trading_pair = TradingPair {
+            coins: self.coins,
+            side: Side::Sell,
+            target: Target::Market,
         };
-        if best_stock_order.is_none() {
-            return Ok(Vec::new());
+        let accountants: Vec<_> = self
+            .merchants_manager
+            .iter()
+            .map(|merchant| merchant.accountant())
+            .collect();
+        let balances = self
+            .fan_out
+            .join(accountants.iter().map(|accountant| {
+                self.fan_out.call(accountant.ask(trading_pair.coin_to_spend()))
+            }))
+            .await;
+        let mut inventory: Decimal = self
+            .my_stock(Side::Sell)
+            .iter()
+            .map(|entity| decimal::from_f64(entity.order.amount))
+            .sum();
+        let merchants = self.merchants_manager.iter();
+        for (merchant, balance) in merchants.zip(balances.into_iter()) {
+            let balance = balance.map_err(|error| {
+                Error::accountant(merchant.id(), trading_pair.clone(), error)
+            })?;
+            inventory += decimal::from_f64(balance.amount);
         }
-        let best_stock_order = best_stock_order.unwrap();
+        Ok(Some(inventory))
+    }
+
+    /// `locked` is what my orders which will be cancelled to free the balance hold of the
+    /// coin to spend.
+    async fn balance(
+        &self,
+        merchant: &dyn Merchant,
+        side: Side,
+        locked: Decimal,
+    ) -> Result<Balance, Error> {
         let market_trading_pair = TradingPair {
-            coins,
+            coins: self.coins,
             side,
             target: Target::Market,
         };
-        let price_for_limit_order = match side {
-            Side::Buy => self.price_calculator.low(best_stock_order.order.price),
-            Side::Sell => self.price_calculator.high(best_stock_order.order.price),
+        let accountant = merchant.accountant();
+        let balance = self
+            .fan_out
+            .call(accountant.ask(market_trading_pair.coin_to_spend()))
+            .await
+            .map_err(|error| Error::accountant(merchant.id(), market_trading_pair, error))?;
+        Ok(self.amount_calculator.balance(
+            decimal::from_f64(balance.amount) + locked,
+            self.fee_model.fee(merchant.id(), Target::Limit),
+        ))
+    }
+
+    /// The limit order as it is sent to the merchant, rounded by its instrument.
+    fn limit_order(
+        &self,
+        merchant_id: MerchantId,
+        side: Side,
+        price: Decimal,
+        amount: Decimal,
+    ) -> Result<Order, Error> {
+        let limit_order = Order {
+            trading_pair: TradingPair {
+                coins: self.coins,
+                side,
+                target: Target::Limit,
+            },
+            price: decimal::to_f64(price),
+            amount: decimal::to_f64(amount),
         };
-        let mut orders = Vec::with_capacity(10);
-        for merchant in self.merchants_manager.iter() {
-            let accountant = merchant.accountant();
-            let balance = accountant.ask(market_trading_pair.coin_to_spend()).await?;
-            let balance = Balance {
-                amount: balance.amount,
-                fee: self.amount_calculator.fee,
-            };
-            let limit_order_amount = match self
-                .amount_calculator
-                .evaluate(best_stock_order.order.amount, &balance) {
-                Some(result) => result,
-                None => return Ok(orders),
-            };
-            let trader = merchant.trader();
-            let limit_order = Order {
-                trading_pair: TradingPair {
-                    coins,
-                    side,
-                    target: Target::Limit,
-                },
-                price: price_for_limit_order,
-                amount: limit_order_amount.value(),
-            };
-            match trader.create_order(limit_order.clone()).await
-            {
-                Ok(Trade::Limit(order)) => {
-                    let merchant_id = self.merchants_manager.get_mercahnt_id(*merchant).unwrap();
-                    let stock = match side {
-                        Side::Buy => &mut self.my_orders_last_state.sell_stock,
-                        Side::Sell => &mut self.my_orders_last_state.buy_stock,
-                    };
-                    let entity = OrderEntity { 
-                        merchant_id, 
-                        order: OrderWithId {
-                            id: order.id,
-                            trading_pair: order.trading_pair,
-                            price: limit_order.price,
-                            amount: limit_order.amount,
-                        }
-                    };
-                    orders.push(entity.clone());
-                    stock.push(entity)
-                }
-                _ => panic!("Failed to create order"),
-            };
+        self.instruments.normalize(merchant_id, limit_order)
+    }
+
+    /// `limit_order` is already rounded, `best_price` is the one of the market order the
+    /// limit order is priced from.
+    async fn create_limit_order(
+        &mut self,
+        merchant: &dyn Merchant,
+        level: usize,
+        limit_order: Order,
+        best_price: Option<Decimal>,
+    ) -> Result<OrderEntity<OrderWithId>, Error> {
+        let side = limit_order.trading_pair.side;
+        self.risk_manager.check(merchant.id(), &limit_order, best_price)?;
+        let trader = merchant.trader();
+        match trader.create_order(limit_order.clone()).await {
+            Ok(Trade::Limit(order)) => {
+                let entity = OrderEntity {
+                    merchant_id: merchant.id(),
+                    order: OrderWithId {
+                        id: order.id,
+                        trading_pair: order.trading_pair,
+                        price: limit_order.price,
+                        amount: limit_order.amount,
+                    },
+                    level,
+                };
+                self.my_stock_mut(side).push(entity.clone());
+                Ok(entity)
+            }
+            Ok(Trade::Market(result)) => Err(Error::trader(
+                merchant.id(),
+                limit_order.trading_pair,
+                format!("Limit order is performed as a market trade {:?}", result),
+            )),
+            Err(error) => Err(Error::trader(merchant.id(), limit_order.trading_pair, error)),
         }
-        println!("{:#?}", orders);
-        Ok(orders)
     }
 
-    pub async fn delete_all_my_orders(&mut self) -> Result<(), String> {
-        self.my_orders_last_state.clear();
-        Deleter::default().delete_all(
+    async fn cancel_order(
+        &mut self,
+        merchant: &dyn Merchant,
+        entity: &OrderEntity<OrderWithId>,
+    ) -> Result<(), Error> {
+        let trader = merchant.trader();
+        trader.delete_order(&entity.order.id)
+            .await
+            .map_err(|error| {
+                Error::trader(merchant.id(), entity.order.trading_pair.clone(), error)
+            })?;
+        self.my_stock_mut(entity.order.trading_pair.side).retain(|item| {
+            item.merchant_id != entity.merchant_id || item.order.id != entity.order.id
+        });
+        Ok(())
+    }
+
+    fn my_stock(&self, side: Side) -> &[OrderEntity<OrderWithId>] {
+        match side {
+            Side::Buy => &self.my_orders_last_state.sell_stock,
+            Side::Sell => &self.my_orders_last_state.buy_stock,
+        }
+    }
+
+    fn my_stock_mut(&mut self, side: Side) -> &mut Vec<OrderEntity<OrderWithId>> {
+        match side {
+            Side::Buy => &mut self.my_orders_last_state.sell_stock,
+            Side::Sell => &mut self.my_orders_last_state.buy_stock,
+        }
+    }
+
+    /// Cancels my tracked orders one by one and then every other order of the coins. The
+    /// orders which failed to be cancelled stay tracked, so their fills are checked.
+    pub async fn delete_all_my_orders(&mut self) -> Result<(), Error> {
+        let min_amount = self.amount_calculator.min_amount_threshold;
+        // The orders filled by check_current_orders are not on the exchange anymore.
+        for side in [Side::Buy, Side::Sell].iter() {
+            self.my_stock_mut(*side)
+                .retain(|entity| decimal::from_f64(entity.order.amount) > min_amount);
+        }
+        let tracked: Vec<_> = self.my_orders().cloned().collect();
+        let mut failure = None;
+        for entity in tracked.iter() {
+            let merchant = self
+                .merchants_manager
+                .merchants()
+                .iter()
+                .find(|merchant| merchant.id() == entity.merchant_id);
+            if let Some(merchant) = merchant {
+                if let Err(error) = self.cancel_order(*merchant, entity).await {
+                    failure = failure.or(Some(error));
+                }
+            }
+        }
+        if let Some(error) = failure {
+            return Err(error);
+        }
+        Deleter { fan_out: self.fan_out }.delete_all(
             self.merchants_manager.iter().as_slice(),
             self.coins.clone(),
-        ).await
+        ).await?;
+        self.my_orders_last_state.clear();
+        Ok(())
     }
 
-    async fn accumulate_merchants_infomration(&self) -> OrdersStorage<Order> {
+    async fn accumulate_merchants_infomration(&self) -> (OrdersStorage<Order>, Vec<Error>) {
+        let fan_out = self.fan_out;
         self.accumulate(|merchant, trading_pair| {
+            let merchant_id = merchant.id();
             let sniffer = merchant.sniffer();
-            let future =
-                async move { sniffer.all_the_best_orders(trading_pair, 15).await.unwrap() };
+            let future = async move {
+                fan_out
+                    .call(sniffer.all_the_best_orders(trading_pair.clone(), 15))
+                    .await
+                    .map_err(|error| Error::sniffer(merchant_id, trading_pair, error))
+            };
             Box::pin(future)
         })
         .await
     }
 
-    async fn accumulate_my_current_order(&self) -> OrdersStorage<OrderWithId> {
+    async fn accumulate_my_current_order(&self) -> (OrdersStorage<OrderWithId>, Vec<Error>) {
+        let fan_out = self.fan_out;
         self.accumulate(|merchant, trading_pair| {
+            let merchant_id = merchant.id();
             let sniffer = merchant.sniffer();
-            let future = async move { sniffer.get_my_orders(trading_pair).await.unwrap() };
+            let future = async move {
+                fan_out
+                    .call(sniffer.get_my_orders(trading_pair.clone()))
+                    .await
+                    .map_err(|error| Error::sniffer(merchant_id, trading_pair, error))
+            };
             Box::pin(future)
         })
         .await
@@ -313,47 +891,46 @@ impl<'a> LimitMaster<'a> {
         sniff_callback: impl Fn(
             &dyn Merchant,
             TradingPair,
-        ) -> std::pin::Pin<Box<dyn futures::Future<Output = TOutput>>>,
-    ) -> OrdersStorage<TOutput::Item> {
+        ) -> std::pin::Pin<Box<dyn futures::Future<Output = Result<TOutput, Error>>>>,
+    ) -> (OrdersStorage<TOutput::Item>, Vec<Error>) {
         let coins = self.coins.clone();
+        let requests: Vec<_> = [Side::Sell, Side::Buy]
+            .iter()
+            .flat_map(|side| self.merchants_manager.iter().map(move |merchant| (*side, *merchant)))
+            .collect();
+        let responses = self
+            .fan_out
+            .join(requests.iter().map(|(side, merchant)| {
+                let trading_pair = TradingPair {
+                    coins,
+                    side: *side,
+                    target: Target::Limit,
+                };
+                sniff_callback(*merchant, trading_pair)
+            }))
+            .await;
         let mut sell_orders_collection = Vec::new();
         let mut buy_orders_collection = Vec::new();
-        for merchant in self.merchants_manager.iter() {
-            let trading_pair = TradingPair {
-                coins,
-                side: Side::Sell,
-                target: Target::Limit,
+        let mut failures = Vec::new();
+        for ((side, merchant), response) in requests.into_iter().zip(responses.into_iter()) {
+            let collection = match side {
+                Side::Sell => &mut sell_orders_collection,
+                Side::Buy => &mut buy_orders_collection,
             };
-            sniff_callback(*merchant, trading_pair)
-                .await
-                .into_iter()
-                .for_each(|order| {
-                    sell_orders_collection.push(OrderEntity::new(
-                        self.merchants_manager.get_mercahnt_id(*merchant).unwrap(),
-                        order,
-                    ))
-                });
+            match response {
+                Ok(orders) => orders
+                    .into_iter()
+                    .for_each(|order| collection.push(OrderEntity::new(merchant.id(), order))),
+                Err(error) => failures.push(error),
+            }
         }
-        for merchant in self.merchants_manager.iter() {
-            let trading_pair = TradingPair {
+        (
+            OrdersStorage {
                 coins,
-                side: Side::Buy,
-                target: Target::Limit,
-            };
-            sniff_callback(*merchant, trading_pair)
-                .await
-                .into_iter()
-                .for_each(|order| {
-                    buy_orders_collection.push(OrderEntity::new(
-                        self.merchants_manager.get_mercahnt_id(*merchant).unwrap(),
-                        order,
-                    ))
-                });
-        }
-        OrdersStorage {
-            coins,
-            buy_stock: buy_orders_collection,
-            sell_stock: sell_orders_collection,
-        }
+                buy_stock: buy_orders_collection,
+                sell_stock: sell_orders_collection,
+            },
+            failures,
+        )
     }
 }
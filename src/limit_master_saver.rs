@@ -0,0 +1,84 @@
+use crate::atomic_file;
+use crate::bookkeeper::{Coins, Side};
+use crate::decimal::Decimal;
+use crate::error::Error;
+use crate::limit_master::LimitMaster;
+use agnostic::trading_pair;
+use std::path::PathBuf;
+
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug, Clone)]
+pub struct OrderSnapshot {
+    pub merchant_id: String,
+    pub id: String,
+    pub side: Side,
+    pub price: Decimal,
+    pub amount: Decimal,
+    /// Rung of the ladder, the snapshots without it are of single orders.
+    #[serde(default)]
+    pub level: usize,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug, Clone)]
+pub struct OrdersSnapshot {
+    pub coins: Coins,
+    pub sell_stock: Vec<OrderSnapshot>,
+    pub buy_stock: Vec<OrderSnapshot>,
+}
+
+/// The file used to contain the snapshot of a single `LimitMaster`.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum SavedSnapshots {
+    Many(Vec<OrdersSnapshot>),
+    One(OrdersSnapshot),
+}
+
+/// Keeps the snapshots of several `LimitMaster`s, one per coins. The file is replaced
+/// as a whole, so a crash leaves either the previous or the new snapshots.
+pub struct LimitMasterSaver {
+    path: PathBuf,
+}
+
+impl LimitMasterSaver {
+    pub fn save_orders(&mut self, limit_master: &LimitMaster) -> Result<(), Error> {
+        let snapshot = limit_master.snapshot();
+        let mut snapshots = self.read_all_orders()?;
+        match snapshots.iter_mut().find(|saved| saved.coins == snapshot.coins) {
+            Some(saved) => *saved = snapshot,
+            None => snapshots.push(snapshot),
+        }
+        Ok(atomic_file::write(&self.path, &serde_json::to_vec(&snapshots)?)?)
+    }
+
+    pub fn load(file: impl Into<PathBuf>) -> Result<LimitMasterSaver, Error> {
+        Ok(LimitMasterSaver { path: file.into() })
+    }
+
+    /// Returns `None` if nothing has been saved for `coins` yet.
+    pub fn read_orders(
+        &mut self,
+        coins: trading_pair::Coins,
+    ) -> Result<Option<OrdersSnapshot>, Error> {
+        let coins = Coins::from(coins);
+        Ok(self
+            .read_all_orders()?
+            .into_iter()
+            .find(|snapshot| snapshot.coins == coins))
+    }
+
+    /// Missing or empty file has no snapshots.
+    pub fn read_all_orders(&mut self) -> Result<Vec<OrdersSnapshot>, Error> {
+        let snapshots = match std::fs::read_to_string(&self.path) {
+            Ok(snapshots) => snapshots,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error.into()),
+        };
+        if snapshots.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        Ok(match serde_json::from_str::<SavedSnapshots>(&snapshots)? {
+            SavedSnapshots::Many(snapshots) => snapshots,
+            SavedSnapshots::One(snapshot) => vec![snapshot],
+        })
+    }
+}
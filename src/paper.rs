@@ -0,0 +1,240 @@
+//! Paper
+//!
+//! Dry runs against the live market. `PaperMerchant` answers the book queries with the
+//! sniffer of the real merchant, but my orders, the trades and the balances are virtual:
+//! they live in an `Exchange` seeded with the balances of the config. Every live book the
+//! sniffer sees is fed to the `Exchange`, so the virtual limit orders are filled once the
+//! live book crosses them. The paper fills are written to a ledger of their own.
+use crate::backtest::exchange::{self, ready, Exchange, Fill, SharedExchange};
+use crate::backtest::replay::Level;
+use crate::bookkeeper::{self, Bookkeeper, Strategy};
+use crate::decimal::{self, Decimal};
+use agnostic::market::{Accountant, Future, Sniffer, Trader};
+use agnostic::merchant::Merchant;
+use agnostic::order::{Order, OrderWithId};
+use agnostic::trade::Trade;
+use agnostic::trading_pair::{Target, TradingPair};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+struct Paper {
+    merchant_id: &'static str,
+    sniffer: Arc<dyn Sniffer>,
+    exchange: SharedExchange,
+    ledger: Option<Ledger>,
+}
+
+impl Paper {
+    /// Depth of the live book fetched before a market order.
+    const MARKET_DEPTH: u32 = 50;
+}
+
+/// The paper fills are written on behalf of `strategy`.
+#[derive(Clone)]
+struct Ledger {
+    bookkeeper: Arc<Mutex<Bookkeeper>>,
+    strategy: Strategy,
+}
+
+impl Ledger {
+    fn lock(&self) -> MutexGuard<'_, Bookkeeper> {
+        self.bookkeeper.lock().expect("Paper ledger is poisoned")
+    }
+}
+
+/// Feeds the live orders to the `Exchange` and writes the fills they have caused.
+fn observe(
+    exchange: &SharedExchange,
+    ledger: Option<&Ledger>,
+    merchant_id: &str,
+    trading_pair: &TradingPair,
+    orders: &[Order],
+) {
+    let mut exchange = exchange.lock();
+    if trading_pair.coins != exchange.coins {
+        return;
+    }
+    let levels = orders
+        .iter()
+        .map(|order| Level {
+            price: decimal::from_f64(order.price),
+            amount: decimal::from_f64(order.amount),
+        })
+        .collect();
+    let filled = exchange.fills().len();
+    let side = exchange::book_side(trading_pair);
+    exchange.update_book_side(side, levels, bookkeeper::now_millis());
+    record(&exchange, filled, ledger, merchant_id);
+}
+
+/// Writes the fills of `exchange` after the first `from` ones.
+fn record(
+    exchange: &Exchange,
+    from: usize,
+    ledger: Option<&Ledger>,
+    merchant_id: &str,
+) {
+    let ledger = match ledger {
+        Some(ledger) => ledger,
+        None => return,
+    };
+    let mut bookkeeper = ledger.lock();
+    for fill in exchange.fills()[from..].iter() {
+        let trade = to_trade(exchange, fill, merchant_id, ledger.strategy);
+        if let Err(error) = bookkeeper.append(&trade) {
+            log::error!("Failed to record the paper fill {:?}: {}", fill, error);
+        }
+    }
+}
+
+fn to_trade(
+    exchange: &Exchange,
+    fill: &Fill,
+    merchant_id: &str,
+    strategy: Strategy,
+) -> bookkeeper::Trade {
+    bookkeeper::Trade {
+        id: fill.order_id.clone(),
+        coins: exchange.coins.into(),
+        side: fill.side.into(),
+        target: fill.target.into(),
+        price: fill.price,
+        amount: fill.amount,
+        time: Some(bookkeeper::now_millis()),
+        merchant_id: Some(merchant_id.to_owned()),
+        strategy: Some(strategy),
+    }
+}
+
+impl Sniffer for Paper {
+    fn all_the_best_orders(
+        &self,
+        trading_pair: TradingPair,
+        count: u32,
+    ) -> Future<Result<Vec<Order>, String>> {
+        let future = self.sniffer.all_the_best_orders(trading_pair.clone(), count);
+        let exchange = self.exchange.clone();
+        let ledger = self.ledger.clone();
+        let merchant_id = self.merchant_id;
+        Box::pin(async move {
+            let orders = future.await?;
+            observe(&exchange, ledger.as_ref(), merchant_id, &trading_pair, &orders);
+            Ok(orders)
+        })
+    }
+
+    /// The virtual orders, the real ones are never reported.
+    fn get_my_orders(
+        &self,
+        trading_pair: TradingPair,
+    ) -> Future<Result<Vec<OrderWithId>, String>> {
+        ready(self.exchange.lock().my_orders(&trading_pair))
+    }
+}
+
+impl Trader for Paper {
+    /// A market order is filled against the live book fetched right before it.
+    fn create_order(&self, order: Order) -> Future<Result<Trade, String>> {
+        let exchange = self.exchange.clone();
+        let ledger = self.ledger.clone();
+        let merchant_id = self.merchant_id;
+        if let Target::Limit = order.trading_pair.target {
+            let mut exchange = exchange.lock();
+            let filled = exchange.fills().len();
+            let trade = exchange.execute(&order);
+            record(&exchange, filled, ledger.as_ref(), merchant_id);
+            return ready(trade);
+        }
+        let book = self
+            .sniffer
+            .all_the_best_orders(order.trading_pair.clone(), Self::MARKET_DEPTH);
+        Box::pin(async move {
+            let orders = book.await?;
+            let trading_pair = &order.trading_pair;
+            observe(&exchange, ledger.as_ref(), merchant_id, trading_pair, &orders);
+            let mut exchange = exchange.lock();
+            let filled = exchange.fills().len();
+            let trade = exchange.execute(&order);
+            record(&exchange, filled, ledger.as_ref(), merchant_id);
+            trade
+        })
+    }
+
+    fn delete_order(&self, id: &str) -> Future<Result<(), String>> {
+        self.exchange.delete_order(id)
+    }
+}
+
+/// Trades the coins of its `Exchange` on paper, the other coins are rejected.
+#[derive(Clone)]
+pub struct PaperMerchant {
+    merchant_id: &'static str,
+    sniffer: Arc<dyn Sniffer>,
+    exchange: SharedExchange,
+    ledger: Option<Ledger>,
+}
+
+impl PaperMerchant {
+    /// `exchange` holds the virtual balances and fees, its book is taken from `merchant`.
+    pub fn new(merchant: &dyn Merchant, exchange: Exchange) -> PaperMerchant {
+        PaperMerchant {
+            merchant_id: merchant.id(),
+            sniffer: merchant.sniffer(),
+            exchange: SharedExchange::new(exchange),
+            ledger: None,
+        }
+    }
+
+    /// The paper fills are written to `ledger` with the id of the real merchant and the
+    /// `strategy` which trades on paper.
+    pub fn with_ledger(mut self, ledger: Bookkeeper, strategy: Strategy) -> Self {
+        self.ledger = Some(Ledger {
+            bookkeeper: Arc::new(Mutex::new(ledger)),
+            strategy,
+        });
+        self
+    }
+
+    pub fn exchange(&self) -> MutexGuard<'_, Exchange> {
+        self.exchange.lock()
+    }
+
+    pub fn ledger(&self) -> Option<MutexGuard<'_, Bookkeeper>> {
+        self.ledger.as_ref().map(Ledger::lock)
+    }
+
+    /// Virtual free plus locked balances of the base and the quote coins.
+    pub fn balances(&self) -> (Decimal, Decimal) {
+        let exchange = self.exchange();
+        let (base, locked_base) = exchange.base();
+        let (quote, locked_quote) = exchange.quote();
+        (base + locked_base, quote + locked_quote)
+    }
+
+    fn paper(&self) -> Arc<Paper> {
+        Arc::new(Paper {
+            merchant_id: self.merchant_id,
+            sniffer: self.sniffer.clone(),
+            exchange: self.exchange.clone(),
+            ledger: self.ledger.clone(),
+        })
+    }
+}
+
+impl Merchant for PaperMerchant {
+    fn id(&self) -> &'static str {
+        self.merchant_id
+    }
+
+    /// The virtual balances.
+    fn accountant(&self) -> Arc<dyn Accountant> {
+        Arc::new(self.exchange.clone())
+    }
+
+    fn trader(&self) -> Arc<dyn Trader> {
+        self.paper()
+    }
+
+    fn sniffer(&self) -> Arc<dyn Sniffer> {
+        self.paper()
+    }
+}
@@ -0,0 +1,823 @@
+//! Recorder
+//!
+//! Captures what the sniffers of the merchants answer. Every response is a binary record
+//! `<length u32 LE><crc32 u32 LE><payload>`, the records go to numbered files
+//! `<prefix>_<index>.records` which are rotated by size or age. The payload keeps the
+//! strings length-prefixed and the numbers as varints, a decimal is its scale and its
+//! mantissa. `RecordReader` streams the records of all the files back in the order they
+//! were written, `to_replay` turns them into the books of a backtest.
+use crate::backtest::replay::{BookSnapshot, Level, Replay};
+use crate::bookkeeper::{self, Coins, Side, Target};
+use crate::decimal::{self, Decimal};
+use crate::error::Error;
+use agnostic::market::{Accountant, Future, Sniffer, Trader};
+use agnostic::merchant::Merchant;
+use agnostic::order::{Order, OrderWithId};
+use agnostic::trading_pair::TradingPair;
+use std::collections::HashMap;
+use std::io::{BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Kind {
+    /// Response of `Sniffer::all_the_best_orders`.
+    BestOrders,
+    /// Response of `Sniffer::get_my_orders`.
+    MyOrders,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug, Clone)]
+pub struct RecordedOrder {
+    /// Only my orders have an id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub price: Decimal,
+    pub amount: Decimal,
+}
+
+impl From<&Order> for RecordedOrder {
+    fn from(order: &Order) -> Self {
+        RecordedOrder {
+            id: None,
+            price: decimal::from_f64(order.price),
+            amount: decimal::from_f64(order.amount),
+        }
+    }
+}
+
+impl From<&OrderWithId> for RecordedOrder {
+    fn from(order: &OrderWithId) -> Self {
+        RecordedOrder {
+            id: Some(order.id.clone()),
+            price: decimal::from_f64(order.price),
+            amount: decimal::from_f64(order.amount),
+        }
+    }
+}
+
+/// A single response of a sniffer, the failed ones carry the error instead of orders.
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug, Clone)]
+pub struct Record {
+    /// Unix time in milliseconds of the response.
+    pub time: i64,
+    pub merchant_id: String,
+    pub coins: Coins,
+    pub side: Side,
+    pub target: Target,
+    pub kind: Kind,
+    #[serde(default)]
+    pub orders: Vec<RecordedOrder>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Record {
+    pub fn new(
+        merchant_id: &str,
+        trading_pair: &TradingPair,
+        kind: Kind,
+        response: Result<Vec<RecordedOrder>, String>,
+    ) -> Record {
+        let (orders, error) = match response {
+            Ok(orders) => (orders, None),
+            Err(error) => (Vec::new(), Some(error)),
+        };
+        Record {
+            time: bookkeeper::now_millis(),
+            merchant_id: merchant_id.to_owned(),
+            coins: trading_pair.coins.into(),
+            side: trading_pair.side.into(),
+            target: trading_pair.target.into(),
+            kind,
+            orders,
+            error,
+        }
+    }
+
+    /// Whether the orders are asks, `None` for the records which are not of the book.
+    fn is_ask(&self) -> Option<bool> {
+        if self.kind != Kind::BestOrders || self.error.is_some() {
+            return None;
+        }
+        Some(match (&self.target, &self.side) {
+            (Target::Market, Side::Buy) | (Target::Limit, Side::Sell) => true,
+            (Target::Market, Side::Sell) | (Target::Limit, Side::Buy) => false,
+        })
+    }
+}
+
+/// A new file is started once any of the limits is reached, no limits by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rotation {
+    pub max_bytes: Option<u64>,
+    pub max_age: Option<Duration>,
+}
+
+struct RecordWriter {
+    directory: PathBuf,
+    prefix: String,
+    rotation: Rotation,
+    /// Created by the first write, so an idle recorder leaves no empty files.
+    file: Option<std::io::BufWriter<std::fs::File>>,
+    index: usize,
+    written: u64,
+    opened: Instant,
+}
+
+impl RecordWriter {
+    /// Continues after the last file of `prefix`, the existing files are never appended.
+    fn open(directory: PathBuf, prefix: String) -> Result<RecordWriter, Error> {
+        std::fs::create_dir_all(&directory)?;
+        let index = record_files(&directory, &prefix)?
+            .last()
+            .map_or(0, |(index, _path)| index + 1);
+        Ok(RecordWriter {
+            file: None,
+            directory,
+            prefix,
+            rotation: Rotation::default(),
+            index,
+            written: 0,
+            opened: Instant::now(),
+        })
+    }
+
+    fn write(&mut self, record: &Record) -> Result<(), Error> {
+        let Rotation { max_bytes, max_age } = self.rotation;
+        let is_full = max_bytes.map_or(false, |max| self.written >= max);
+        let is_old = max_age.map_or(false, |max| self.opened.elapsed() >= max);
+        if self.written > 0 && (is_full || is_old) {
+            self.rotate()?;
+        }
+        let frame = encode_frame(record);
+        let file = match self.file.as_mut() {
+            Some(file) => file,
+            None => {
+                self.opened = Instant::now();
+                let file = create_file(&self.directory, &self.prefix, self.index)?;
+                self.file.get_or_insert(file)
+            }
+        };
+        file.write_all(&frame)?;
+        self.written += frame.len() as u64;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        if let Some(file) = self.file.as_mut() {
+            file.flush()?;
+        }
+        Ok(())
+    }
+
+    /// The next file is created by the next write.
+    fn rotate(&mut self) -> Result<(), Error> {
+        if let Some(mut file) = self.file.take() {
+            file.flush()?;
+        }
+        self.index += 1;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+const EXTENSION: &str = "records";
+/// Larger lengths are taken for a corrupted frame.
+const MAX_FRAME: u32 = 64 * 1024 * 1024;
+
+fn encode_frame(record: &Record) -> Vec<u8> {
+    let mut payload = Vec::new();
+    encode_record(record, &mut payload);
+    let mut frame = Vec::with_capacity(payload.len() + 8);
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    frame.extend_from_slice(&payload);
+    frame
+}
+
+/// Reads the next frame, `None` at the end of the file. A torn frame is an error.
+fn read_frame(
+    file: &mut std::io::BufReader<std::fs::File>,
+) -> Result<Option<(u32, Vec<u8>)>, Error> {
+    if file.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+    let mut header = [0u8; 8];
+    file.read_exact(&mut header)?;
+    let length = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    let checksum = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    if length > MAX_FRAME {
+        return Err(corrupted(format!("Frame of {} bytes", length)));
+    }
+    let mut payload = vec![0u8; length as usize];
+    file.read_exact(&mut payload)?;
+    Ok(Some((checksum, payload)))
+}
+
+fn corrupted(source: String) -> Error {
+    Error::Persistence {
+        merchant_id: None,
+        trading_pair: None,
+        source,
+    }
+}
+
+const BUY: u8 = 1;
+const LIMIT: u8 = 1 << 1;
+const MY_ORDERS: u8 = 1 << 2;
+const FAILED: u8 = 1 << 3;
+
+fn encode_record(record: &Record, payload: &mut Vec<u8>) {
+    put_varint(payload, zigzag(record.time as i128));
+    put_str(payload, &record.merchant_id);
+    put_str(payload, &record.coins.base);
+    put_str(payload, &record.coins.quote);
+    let mut flags = 0;
+    if record.side == Side::Buy {
+        flags |= BUY;
+    }
+    if record.target == Target::Limit {
+        flags |= LIMIT;
+    }
+    if record.kind == Kind::MyOrders {
+        flags |= MY_ORDERS;
+    }
+    if record.error.is_some() {
+        flags |= FAILED;
+    }
+    payload.push(flags);
+    put_varint(payload, record.orders.len() as u128);
+    for order in record.orders.iter() {
+        match &order.id {
+            Some(id) => {
+                payload.push(1);
+                put_str(payload, id);
+            }
+            None => payload.push(0),
+        }
+        put_decimal(payload, order.price);
+        put_decimal(payload, order.amount);
+    }
+    if let Some(error) = &record.error {
+        put_str(payload, error);
+    }
+}
+
+fn decode_record(payload: &[u8]) -> Result<Record, String> {
+    let mut bytes = Bytes(payload);
+    let time = unzigzag(bytes.varint()?) as i64;
+    let merchant_id = bytes.string()?;
+    let coins = Coins {
+        base: bytes.string()?,
+        quote: bytes.string()?,
+    };
+    let flags = bytes.byte()?;
+    let count = bytes.varint()?;
+    let mut orders = Vec::new();
+    for _ in 0..count {
+        let id = match bytes.byte()? {
+            0 => None,
+            _ => Some(bytes.string()?),
+        };
+        orders.push(RecordedOrder {
+            id,
+            price: bytes.decimal()?,
+            amount: bytes.decimal()?,
+        });
+    }
+    let error = match flags & FAILED {
+        0 => None,
+        _ => Some(bytes.string()?),
+    };
+    if !bytes.0.is_empty() {
+        return Err(format!("{} trailing bytes", bytes.0.len()));
+    }
+    Ok(Record {
+        time,
+        merchant_id,
+        coins,
+        side: if flags & BUY != 0 { Side::Buy } else { Side::Sell },
+        target: if flags & LIMIT != 0 { Target::Limit } else { Target::Market },
+        kind: if flags & MY_ORDERS != 0 { Kind::MyOrders } else { Kind::BestOrders },
+        orders,
+        error,
+    })
+}
+
+fn zigzag(value: i128) -> u128 {
+    ((value << 1) ^ (value >> 127)) as u128
+}
+
+fn unzigzag(value: u128) -> i128 {
+    (value >> 1) as i128 ^ -((value & 1) as i128)
+}
+
+fn put_varint(payload: &mut Vec<u8>, mut value: u128) {
+    while value >= 0x80 {
+        payload.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    payload.push(value as u8);
+}
+
+fn put_str(payload: &mut Vec<u8>, value: &str) {
+    put_varint(payload, value.len() as u128);
+    payload.extend_from_slice(value.as_bytes());
+}
+
+fn put_decimal(payload: &mut Vec<u8>, value: Decimal) {
+    payload.push(value.scale() as u8);
+    put_varint(payload, zigzag(value.mantissa()));
+}
+
+/// The payload left to decode.
+struct Bytes<'b>(&'b [u8]);
+
+impl<'b> Bytes<'b> {
+    fn take(&mut self, count: usize) -> Result<&'b [u8], String> {
+        if self.0.len() < count {
+            return Err("Truncated record".to_owned());
+        }
+        let (taken, rest) = self.0.split_at(count);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<u128, String> {
+        let mut value = 0u128;
+        for shift in (0..128).step_by(7) {
+            let byte = self.byte()?;
+            value |= u128::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("Varint is too long".to_owned())
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let length = self.varint()? as usize;
+        let bytes = self.take(length)?;
+        String::from_utf8(bytes.to_vec()).map_err(|error| error.to_string())
+    }
+
+    fn decimal(&mut self) -> Result<Decimal, String> {
+        let scale = u32::from(self.byte()?);
+        let mantissa = unzigzag(self.varint()?);
+        Decimal::try_from_i128_with_scale(mantissa, scale)
+            .map_err(|error| error.to_string())
+    }
+}
+
+fn file_path(directory: &Path, prefix: &str, index: usize) -> PathBuf {
+    directory.join(format!("{}_{:06}.{}", prefix, index, EXTENSION))
+}
+
+fn create_file(
+    directory: &Path,
+    prefix: &str,
+    index: usize,
+) -> Result<std::io::BufWriter<std::fs::File>, Error> {
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(file_path(directory, prefix, index))?;
+    Ok(std::io::BufWriter::new(file))
+}
+
+/// Record files of `prefix` sorted by their index.
+fn record_files(directory: &Path, prefix: &str) -> Result<Vec<(usize, PathBuf)>, Error> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.extension().map_or(true, |extension| extension != EXTENSION) {
+            continue;
+        }
+        let index = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.strip_prefix(prefix))
+            .and_then(|rest| rest.strip_prefix('_'))
+            .and_then(|index| index.parse::<usize>().ok());
+        if let Some(index) = index {
+            files.push((index, path));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+enum Command {
+    Record(Record),
+    Rotate(Rotation),
+    /// Answers once the records sent before are in the files.
+    Flush(mpsc::Sender<Result<(), Error>>),
+}
+
+/// Owns the `RecordWriter`, which is buffered and only flushed on demand, on rotation and
+/// when the recorder is dropped.
+fn run_writer(mut writer: RecordWriter, commands: mpsc::Receiver<Command>) {
+    for command in commands.iter() {
+        match command {
+            Command::Record(record) => {
+                if let Err(error) = writer.write(&record) {
+                    log::warn!("Failed to record {:?}: {}", record.kind, error);
+                }
+            }
+            Command::Rotate(rotation) => writer.rotation = rotation,
+            Command::Flush(flushed) => {
+                let _ = flushed.send(writer.flush());
+            }
+        }
+    }
+    if let Err(error) = writer.flush() {
+        log::warn!("Failed to flush the records: {}", error);
+    }
+}
+
+struct WriterThread {
+    /// Taken on drop, so the thread runs out of commands.
+    commands: Mutex<Option<mpsc::Sender<Command>>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Drop for WriterThread {
+    fn drop(&mut self) {
+        if let Ok(commands) = self.commands.get_mut() {
+            commands.take();
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Shared by the wrapped sniffers, the records of all of them go to the same files. The
+/// files are written by a thread of their own, so the sniffers never wait for the disk.
+#[derive(Clone)]
+pub struct Recorder {
+    writer: Arc<WriterThread>,
+}
+
+impl Recorder {
+    pub fn open(directory: impl Into<PathBuf>, prefix: &str) -> Result<Recorder, Error> {
+        let writer = RecordWriter::open(directory.into(), prefix.to_owned())?;
+        let (commands, receiver) = mpsc::channel();
+        let thread = std::thread::Builder::new()
+            .name("recorder".to_owned())
+            .spawn(move || run_writer(writer, receiver))?;
+        Ok(Recorder {
+            writer: Arc::new(WriterThread {
+                commands: Mutex::new(Some(commands)),
+                thread: Some(thread),
+            }),
+        })
+    }
+
+    pub fn with_rotation(self, rotation: Rotation) -> Self {
+        if let Err(error) = self.send(Command::Rotate(rotation)) {
+            log::warn!("Failed to set the rotation: {}", error);
+        }
+        self
+    }
+
+    /// A failed write is logged, the sniffers answer anyway.
+    pub fn record(&self, record: Record) {
+        let kind = record.kind;
+        if let Err(error) = self.send(Command::Record(record)) {
+            log::warn!("Failed to record {:?}: {}", kind, error);
+        }
+    }
+
+    /// Waits until the records sent before are written to the files.
+    pub fn flush(&self) -> Result<(), Error> {
+        let (flushed, receiver) = mpsc::channel();
+        self.send(Command::Flush(flushed))?;
+        receiver.recv().map_err(|_| stopped())?
+    }
+
+    /// `merchant` with the sniffer which records its responses.
+    pub fn wrap<'m>(&self, merchant: &'m dyn Merchant) -> RecordingMerchant<'m> {
+        RecordingMerchant {
+            merchant,
+            sniffer: Arc::new(RecordingSniffer {
+                sniffer: merchant.sniffer(),
+                merchant_id: merchant.id(),
+                recorder: self.clone(),
+            }),
+        }
+    }
+
+    fn send(&self, command: Command) -> Result<(), Error> {
+        let commands = self.writer.commands.lock().expect("Recorder is poisoned");
+        match commands.as_ref() {
+            Some(commands) => commands.send(command).map_err(|_| stopped()),
+            None => Err(stopped()),
+        }
+    }
+}
+
+fn stopped() -> Error {
+    Error::persistence("Record writer has stopped")
+}
+
+pub struct RecordingSniffer {
+    sniffer: Arc<dyn Sniffer>,
+    merchant_id: &'static str,
+    recorder: Recorder,
+}
+
+impl Sniffer for RecordingSniffer {
+    fn all_the_best_orders(
+        &self,
+        trading_pair: TradingPair,
+        count: u32,
+    ) -> Future<Result<Vec<Order>, String>> {
+        let future = self.sniffer.all_the_best_orders(trading_pair.clone(), count);
+        let (merchant_id, recorder) = (self.merchant_id, self.recorder.clone());
+        Box::pin(async move {
+            let response = future.await;
+            let orders = match &response {
+                Ok(orders) => Ok(orders.iter().map(RecordedOrder::from).collect()),
+                Err(error) => Err(error.clone()),
+            };
+            let record =
+                Record::new(merchant_id, &trading_pair, Kind::BestOrders, orders);
+            recorder.record(record);
+            response
+        })
+    }
+
+    fn get_my_orders(
+        &self,
+        trading_pair: TradingPair,
+    ) -> Future<Result<Vec<OrderWithId>, String>> {
+        let future = self.sniffer.get_my_orders(trading_pair.clone());
+        let (merchant_id, recorder) = (self.merchant_id, self.recorder.clone());
+        Box::pin(async move {
+            let response = future.await;
+            let orders = match &response {
+                Ok(orders) => Ok(orders.iter().map(RecordedOrder::from).collect()),
+                Err(error) => Err(error.clone()),
+            };
+            let record = Record::new(merchant_id, &trading_pair, Kind::MyOrders, orders);
+            recorder.record(record);
+            response
+        })
+    }
+}
+
+/// The merchant as is, except for the recording sniffer.
+pub struct RecordingMerchant<'m> {
+    merchant: &'m dyn Merchant,
+    sniffer: Arc<RecordingSniffer>,
+}
+
+impl<'m> Merchant for RecordingMerchant<'m> {
+    fn id(&self) -> &'static str {
+        self.merchant.id()
+    }
+
+    fn accountant(&self) -> Arc<dyn Accountant> {
+        self.merchant.accountant()
+    }
+
+    fn trader(&self) -> Arc<dyn Trader> {
+        self.merchant.trader()
+    }
+
+    fn sniffer(&self) -> Arc<dyn Sniffer> {
+        self.sniffer.clone()
+    }
+}
+
+/// Streams the records of all the files of a prefix. A record which fails its checksum
+/// is returned as an error and the reading goes on with the next one, a torn record ends
+/// its file.
+pub struct RecordReader {
+    files: std::vec::IntoIter<(usize, PathBuf)>,
+    file: Option<std::io::BufReader<std::fs::File>>,
+}
+
+impl RecordReader {
+    pub fn open(
+        directory: impl AsRef<Path>,
+        prefix: &str,
+    ) -> Result<RecordReader, Error> {
+        Ok(RecordReader {
+            files: record_files(directory.as_ref(), prefix)?.into_iter(),
+            file: None,
+        })
+    }
+}
+
+impl Iterator for RecordReader {
+    type Item = Result<Record, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(file) = self.file.as_mut() {
+                match read_frame(file) {
+                    Ok(Some((checksum, payload))) => {
+                        if crc32fast::hash(&payload) != checksum {
+                            return Some(Err(corrupted("Checksum mismatch".to_owned())));
+                        }
+                        return Some(decode_record(&payload).map_err(corrupted));
+                    }
+                    Ok(None) => self.file = None,
+                    Err(error) => {
+                        self.file = None;
+                        return Some(Err(error));
+                    }
+                }
+            }
+            let (_index, path) = self.files.next()?;
+            match std::fs::File::open(&path) {
+                Ok(file) => self.file = Some(std::io::BufReader::new(file)),
+                Err(error) => return Some(Err(error.into())),
+            }
+        }
+    }
+}
+
+/// Books of `merchant_id`: a snapshot is made every time both the bids and the asks of
+/// the coins have been answered since the previous one.
+pub fn to_replay(records: impl IntoIterator<Item = Record>, merchant_id: &str) -> Replay {
+    let mut sides: HashMap<Coins, (Option<Vec<Level>>, Option<Vec<Level>>)> =
+        HashMap::new();
+    let mut snapshots = Vec::new();
+    for record in records.into_iter() {
+        if record.merchant_id != merchant_id {
+            continue;
+        }
+        let is_ask = match record.is_ask() {
+            Some(is_ask) => is_ask,
+            None => continue,
+        };
+        let mut levels: Vec<_> = record
+            .orders
+            .iter()
+            .map(|order| Level {
+                price: order.price,
+                amount: order.amount,
+            })
+            .collect();
+        let (bids, asks) = sides.entry(record.coins.clone()).or_default();
+        if is_ask {
+            levels.sort_by(|left, right| left.price.cmp(&right.price));
+            *asks = Some(levels);
+        } else {
+            levels.sort_by(|left, right| right.price.cmp(&left.price));
+            *bids = Some(levels);
+        }
+        if bids.is_some() && asks.is_some() {
+            snapshots.push(BookSnapshot {
+                time: record.time,
+                coins: record.coins,
+                bids: bids.take().unwrap_or_default(),
+                asks: asks.take().unwrap_or_default(),
+            });
+        }
+    }
+    Replay::new(snapshots)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use agnostic::trading_pair;
+    use rust_decimal_macros::dec;
+
+    fn record(side: trading_pair::Side, price: Decimal) -> Record {
+        let trading_pair = TradingPair {
+            coins: trading_pair::Coins::TonUsdt,
+            side,
+            target: trading_pair::Target::Limit,
+        };
+        let order = RecordedOrder {
+            id: None,
+            price,
+            amount: dec!(1),
+        };
+        Record::new("first", &trading_pair, Kind::BestOrders, Ok(vec![order]))
+    }
+
+    #[test]
+    fn rotation_and_reading() {
+        let directory = std::env::temp_dir().join("open_midas_recorder_rotation");
+        let _ = std::fs::remove_dir_all(&directory);
+        let recorder = Recorder::open(&directory, "books")
+            .expect("Failed to open recorder")
+            .with_rotation(Rotation {
+                max_bytes: Some(1),
+                max_age: None,
+            });
+        // The file is created by the first record.
+        assert!(record_files(&directory, "books").unwrap().is_empty());
+        let records = vec![
+            record(trading_pair::Side::Sell, dec!(1.01)),
+            record(trading_pair::Side::Buy, dec!(0.99)),
+            record(trading_pair::Side::Sell, dec!(1.02)),
+        ];
+        records.iter().for_each(|record| recorder.record(record.clone()));
+        recorder.flush().expect("Failed to flush");
+        assert_eq!(record_files(&directory, "books").unwrap().len(), 3);
+        // A new recorder never appends to the files of the previous one.
+        let reopened = Recorder::open(&directory, "books").expect("Failed to reopen");
+        assert_eq!(record_files(&directory, "books").unwrap().len(), 3);
+        let last = record(trading_pair::Side::Buy, dec!(0.98));
+        reopened.record(last.clone());
+        reopened.flush().expect("Failed to flush");
+        assert_eq!(record_files(&directory, "books").unwrap().len(), 4);
+
+        let mut read: Vec<_> = RecordReader::open(&directory, "books")
+            .expect("Failed to open reader")
+            .collect::<Result<_, _>>()
+            .expect("Failed to read records");
+        assert_eq!(read.pop(), Some(last));
+        assert_eq!(read, records);
+
+        let replay = to_replay(read, "first");
+        assert_eq!(replay.len(), 1);
+        assert_eq!(replay.snapshots[0].asks[0].price, dec!(1.01));
+        assert_eq!(replay.snapshots[0].bids[0].price, dec!(0.99));
+        assert!(to_replay(records, "second").is_empty());
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn compact_records() {
+        let trading_pair = TradingPair {
+            coins: trading_pair::Coins::TonUsdt,
+            side: trading_pair::Side::Sell,
+            target: trading_pair::Target::Market,
+        };
+        let orders = (0..50)
+            .map(|level| RecordedOrder {
+                id: if level % 2 == 0 { Some(level.to_string()) } else { None },
+                price: dec!(1.2345) + Decimal::new(level, 4),
+                amount: dec!(150.5) * Decimal::from(level),
+            })
+            .collect();
+        let records = vec![
+            Record::new("first", &trading_pair, Kind::MyOrders, Ok(orders)),
+            Record::new("first", &trading_pair, Kind::BestOrders, Err("Timeout".into())),
+        ];
+        let json: usize = records
+            .iter()
+            .map(|record| serde_json::to_vec(record).unwrap().len() + 1)
+            .sum();
+        let directory = std::env::temp_dir().join("open_midas_recorder_size");
+        let _ = std::fs::remove_dir_all(&directory);
+        let recorder = Recorder::open(&directory, "books").expect("Failed to open");
+        records.iter().for_each(|record| recorder.record(record.clone()));
+        recorder.flush().expect("Failed to flush");
+        let files = record_files(&directory, "books").unwrap();
+        assert_eq!(files.len(), 1);
+        let size = std::fs::metadata(&files[0].1).unwrap().len() as usize;
+        assert!(size * 3 < json, "{} bytes against {} bytes of JSON", size, json);
+
+        let read: Vec<_> = RecordReader::open(&directory, "books")
+            .expect("Failed to open reader")
+            .collect::<Result<_, _>>()
+            .expect("Failed to read records");
+        assert_eq!(read, records);
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn corrupted_record_is_skipped() {
+        let directory = std::env::temp_dir().join("open_midas_recorder_corrupted");
+        let _ = std::fs::remove_dir_all(&directory);
+        let recorder = Recorder::open(&directory, "books").expect("Failed to open");
+        let records = vec![
+            record(trading_pair::Side::Sell, dec!(1.01)),
+            record(trading_pair::Side::Buy, dec!(0.99)),
+        ];
+        records.iter().for_each(|record| recorder.record(record.clone()));
+        drop(recorder);
+        let path = record_files(&directory, "books").unwrap()[0].1.clone();
+        let mut bytes = std::fs::read(&path).unwrap();
+        // The last byte of the first payload.
+        let first = encode_frame(&records[0]).len();
+        bytes[first - 1] ^= 0xff;
+        // A torn frame at the end.
+        bytes.extend_from_slice(&encode_frame(&records[0])[..5]);
+        std::fs::write(&path, bytes).unwrap();
+
+        let read: Vec<_> = RecordReader::open(&directory, "books")
+            .expect("Failed to open reader")
+            .collect();
+        assert_eq!(read.len(), 3);
+        assert!(read[0].is_err());
+        assert_eq!(read[1].as_ref().ok(), Some(&records[1]));
+        assert!(read[2].is_err());
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+}
@@ -1,15 +1,21 @@
 use crate::calculators::amount_calculator::Balance;
-use crate::calculators::{AmountCalculator, ProfitCalculator};
+use crate::calculators::{AmountCalculator, FeeModel, ProfitCalculator};
+use crate::decimal::{self, Decimal};
+use crate::error::Error;
+use crate::fan_out::FanOut;
+use crate::instrument::Instruments;
+use crate::risk::RiskManager;
 use crate::filters::LowAmountFilter;
+use crate::limit_master::OrderEntity;
 use agnostic::merchant::Merchant;
 use agnostic::order::Order;
-use agnostic::trade::{Trade, TradeResult};
-use agnostic::trading_pair::{Coin, Coins, TradingPair};
+use agnostic::trade::Trade;
+use agnostic::trading_pair::{Coins, TradingPair};
 use agnostic::trading_pair::{Side, Target};
 use std::collections::HashMap;
 
-pub type Price = f64;
-pub type Amount = f64;
+pub type Price = Decimal;
+pub type Amount = Decimal;
 pub type Storage = HashMap<Coins, Vec<Entry>>;
 
 #[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
@@ -19,7 +25,7 @@ pub struct Entry {
 }
 
 impl Entry {
-    pub fn incremented(&mut self, amount: f64) {
+    pub fn incremented(&mut self, amount: Amount) {
         self.amount += amount;
     }
 }
@@ -30,8 +36,12 @@ pub struct Reseller<'a> {
     pub sell_storage: Storage,
     low_amount_filter: LowAmountFilter,
     amount_calculator: AmountCalculator,
-    min_profit: f64,
+    min_profit: Decimal,
     auto_accept: bool,
+    fan_out: FanOut,
+    fee_model: FeeModel,
+    instruments: Instruments,
+    risk_manager: RiskManager,
 }
 
 impl<'a> Reseller<'a> {
@@ -41,7 +51,7 @@ impl<'a> Reseller<'a> {
         merchants: Vec<&'a dyn Merchant>,
         low_amount_filter: LowAmountFilter,
         amount_calculator: AmountCalculator,
-        min_profit: f64,
+        min_profit: Decimal,
         auto_accept: bool,
     ) -> Reseller<'a> {
         Reseller {
@@ -52,13 +62,40 @@ impl<'a> Reseller<'a> {
             sell_storage,
             min_profit,
             auto_accept,
+            fan_out: FanOut::default(),
+            fee_model: FeeModel::default(),
+            instruments: Instruments::default(),
+            risk_manager: RiskManager::default(),
         }
     }
 
+    pub fn with_fan_out(mut self, fan_out: FanOut) -> Self {
+        self.fan_out = fan_out;
+        self
+    }
+
+    /// `min_profit` is net of the fees of the model, which are zero by default.
+    pub fn with_fee_model(mut self, fee_model: FeeModel) -> Self {
+        self.fee_model = fee_model;
+        self
+    }
+
+    /// The planned orders are rounded to the specs of the merchants before they are sent.
+    pub fn with_instruments(mut self, instruments: Instruments) -> Self {
+        self.instruments = instruments;
+        self
+    }
+
+    /// The planned orders are checked against the best order they would take.
+    pub fn with_risk_manager(mut self, risk_manager: RiskManager) -> Self {
+        self.risk_manager = risk_manager;
+        self
+    }
+
     pub fn accept_trade(&mut self, trade: Trade) {
         let coins = trade.trading_pair().coins;
-        let price = trade.price();
-        let amount = trade.amount();
+        let price = decimal::from_f64(trade.price());
+        let amount = decimal::from_f64(trade.amount());
         let storage: &mut Storage = match trade.trading_pair().side {
             Side::Sell => &mut self.sell_storage,
             Side::Buy => &mut self.buy_storage,
@@ -66,7 +103,9 @@ impl<'a> Reseller<'a> {
         accept_new_item(storage, &coins, price, amount)
     }
 
-    pub async fn iterate(&mut self) -> Result<Option<Trade>, String> {
+    /// Returns the trades and the merchants they were performed on. An entry may be sold on
+    /// several merchants at once.
+    pub async fn iterate(&mut self) -> Result<Vec<OrderEntity<Trade>>, Error> {
         let target = Target::Market;
         for iteration_side in &[Side::Sell, Side::Buy] {
             let (storage, entry_side) = match iteration_side {
@@ -89,68 +128,98 @@ impl<'a> Reseller<'a> {
                     target,
                     side: iteration_side.clone(),
                 };
-                let (the_best_order, merchant) = match find_the_best_order(
+                let plans = match find_the_best_orders(
                     the_best_entry,
                     &self.merchants,
                     trading_pair,
                     &self.amount_calculator,
                     &self.low_amount_filter,
+                    &self.fee_model,
+                    self.min_profit,
+                    &self.fan_out,
                 )
                 .await
                 {
-                    Ok(find_result) => find_result,
+                    Ok(plans) => plans,
                     Err(error) => match error {
                         FindError::NoProfit => continue,
-                        other => {
-                            return Err(format!("Find error: {}", other));
-                        }
+                        FindError::Exchange(error) => return Err(error),
                     },
                 };
-                log::debug!(
-                    "The best order: Side {:<8} Price {:^10.3} Amount {}",
-                    the_best_order.trading_pair.side,
-                    the_best_order.price,
-                    the_best_order.amount);
-                let profit_calculator = ProfitCalculator::default();
-                let (sell_price, buy_price) = match iteration_side.clone() {
-                    Side::Sell => (the_best_order.price, the_best_entry.price),
-                    Side::Buy => (the_best_entry.price, the_best_order.price),
-                };
-                match profit_calculator.evaluate(sell_price, buy_price) {
-                    Some(profit) => {
-                        if profit >= self.min_profit {
-                            let trader = merchant.trader();
-                            match trader.create_order(the_best_order.clone()).await {
-                                Ok(trade) => {
-                                    let trade_amount = the_best_order.amount;
-                                    if the_best_entry.amount - trade_amount <= 0.0 {
-                                        entries.remove(entry_index);
-                                    } else {
-                                        let entry = entries.get_mut(entry_index).unwrap();
-                                        entry.amount -= trade_amount
-                                    };
-                                    if self.auto_accept {
-                                        self.accept_trade(Trade::Market(TradeResult {
-                                            id: trade.id(),
-                                            trading_pair: trade.trading_pair(),
-                                            price: the_best_order.price,
-                                            amount: trade_amount
-                                        }))
-                                    }
-                                    return Ok(Some(trade));
-                                }
-                                Err(error) => return Err(format!(
-                                    "Failed to create an order {:#?}\n\t Error!: {:#?}",
-                                    the_best_order, error
-                                )),
-                            }
+                let mut failure = None;
+                let mut orders = Vec::with_capacity(plans.len());
+                for (plan, merchant) in plans.iter() {
+                    let best_price = plan
+                        .levels
+                        .first()
+                        .map(|level| decimal::from_f64(level.price));
+                    let checked = self
+                        .instruments
+                        .normalize(merchant.id(), plan.order())
+                        .and_then(|order| {
+                            self.risk_manager
+                                .check(merchant.id(), &order, best_price)
+                                .map(|()| order)
+                        });
+                    match checked {
+                        Ok(order) => orders.push((plan, order, *merchant)),
+                        Err(error) => {
+                            log::error!("{}", error);
+                            failure = failure.or(Some(error));
+                        }
+                    }
+                }
+                let results = self
+                    .fan_out
+                    .join(orders.iter().map(|(plan, order, merchant)| {
+                        log::info!(
+                            "Plan on {}: Side {:<8} Levels {:^4} Amount {:^10.3} VWAP {:^10.3}",
+                            merchant.id(),
+                            iteration_side,
+                            plan.levels.len(),
+                            order.amount,
+                            plan.average_price);
+                        merchant.trader().create_order(order.clone())
+                    }))
+                    .await;
+                let mut trades = Vec::with_capacity(orders.len());
+                for ((_plan, order, merchant), result) in orders.iter().zip(results) {
+                    match result {
+                        Ok(trade) => trades.push(OrderEntity::new(merchant.id(), trade)),
+                        Err(error) => {
+                            let error = Error::trader(
+                                merchant.id(),
+                                order.trading_pair.clone(),
+                                format!("Failed to create an order {:#?}: {}", order, error),
+                            );
+                            log::error!("{}", error);
+                            failure = failure.or(Some(error));
                         }
                     }
-                    None => continue,
                 }
+                if trades.is_empty() {
+                    return Err(failure.expect("Every plan has a result"));
+                }
+                // A market order may be filled partially, only the filled amount is sold.
+                let filled: Amount = trades
+                    .iter()
+                    .map(|entity| decimal::from_f64(entity.order.amount()))
+                    .sum();
+                if the_best_entry.amount - filled <= Decimal::ZERO {
+                    entries.remove(entry_index);
+                } else {
+                    let entry = entries.get_mut(entry_index).unwrap();
+                    entry.amount -= filled
+                };
+                if self.auto_accept {
+                    for entity in trades.iter() {
+                        self.accept_trade(entity.order.clone())
+                    }
+                }
+                return Ok(trades);
             }
         }
-        Ok(None)
+        Ok(Vec::new())
     }
 }
 
@@ -181,106 +250,266 @@ fn find_best_entry(entries: &[Entry], side: Side) -> Option<(usize, &Entry)> {
         Side::Sell => entries
             .iter()
             .enumerate()
-            .min_by(|left, right| left.1.price.partial_cmp(&right.1.price).unwrap()),
+            .min_by(|left, right| left.1.price.cmp(&right.1.price)),
         Side::Buy => entries
             .iter()
             .enumerate()
-            .max_by(|left, right| left.1.price.partial_cmp(&right.1.price).unwrap()),
+            .max_by(|left, right| left.1.price.cmp(&right.1.price)),
+    }
+}
+
+/// The levels of a merchant's book with the balance to take them.
+pub struct Book<'b> {
+    pub orders: &'b [Order],
+    pub balance: &'b Balance,
+    /// Fees of the entry and of the levels.
+    pub profit_calculator: ProfitCalculator,
+}
+
+/// The levels of the book an order is spread over.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExecutionPlan {
+    /// Every level with the amount taken from it.
+    pub levels: Vec<Order>,
+    pub amount: Amount,
+    /// Volume weighted average price of the levels.
+    pub average_price: Price,
+    pub worst_price: Price,
+}
+
+impl ExecutionPlan {
+    /// Walks the book from the best level while the margin of the average price against
+    /// the entry stays above `min_profit`. The amount is limited by the entry and by the
+    /// balance of the coin to spend.
+    pub fn walk(
+        entry: &Entry,
+        book: Book,
+        min_profit: Decimal,
+    ) -> Option<ExecutionPlan> {
+        Self::route(entry, &[book], min_profit).pop().flatten()
+    }
+
+    /// Walks the books of several merchants at once, always taking the best level left.
+    /// The margin is checked against the average price of all the plans together, while
+    /// every book is limited by its own balance. The plans are in the order of `books`.
+    pub fn route(
+        entry: &Entry,
+        books: &[Book],
+        min_profit: Decimal,
+    ) -> Vec<Option<ExecutionPlan>> {
+        let mut levels: Vec<(usize, &Order, Price, Price)> = books
+            .iter()
+            .enumerate()
+            .flat_map(|(index, book)| {
+                let calculator = &book.profit_calculator;
+                book.orders.iter().map(move |order| {
+                    let price = decimal::from_f64(order.price);
+                    let (sell_price, buy_price) = match order.trading_pair.side {
+                        Side::Sell => (price, entry.price),
+                        Side::Buy => (entry.price, price),
+                    };
+                    (
+                        index,
+                        order,
+                        calculator.net_sell_price(sell_price),
+                        calculator.net_buy_price(buy_price),
+                    )
+                })
+            })
+            .collect();
+        // The sort is stable, so the first book wins the levels of the same price.
+        levels.sort_by(|(_, order, left_sell, left_buy), (_, _, right_sell, right_buy)| {
+            match order.trading_pair.side {
+                Side::Sell => right_sell.cmp(left_sell),
+                Side::Buy => left_buy.cmp(right_buy),
+            }
+        });
+        let profit_calculator = ProfitCalculator::default();
+        let mut plans: Vec<ExecutionPlan> = books.iter().map(|_| Self::empty()).collect();
+        let mut budgets: Vec<Decimal> =
+            books.iter().map(|book| book.balance.with_fee()).collect();
+        let mut amount = Decimal::ZERO;
+        let mut sell_volume = Decimal::ZERO;
+        let mut buy_volume = Decimal::ZERO;
+        for (book, order, sell_price, buy_price) in levels {
+            if entry.amount - amount <= Decimal::ZERO {
+                break;
+            }
+            let pair = &order.trading_pair;
+            let affordable = agnostic::price::convert_to_base_coin_amount(
+                pair.target.clone(),
+                pair.side.clone(),
+                &order.price.into(),
+                decimal::to_f64(budgets[book]),
+            );
+            let affordable = decimal::from_f64(affordable);
+            let level_amount = decimal::from_f64(order.amount)
+                .min(entry.amount - amount)
+                .min(affordable);
+            if level_amount <= Decimal::ZERO {
+                continue;
+            }
+            let total_amount = amount + level_amount;
+            match profit_calculator.evaluate(
+                (sell_volume + sell_price * level_amount) / total_amount,
+                (buy_volume + buy_price * level_amount) / total_amount,
+            ) {
+                Some(profit) if profit >= min_profit => (),
+                _ => break,
+            }
+            budgets[book] -= budgets[book] * level_amount / affordable;
+            sell_volume += sell_price * level_amount;
+            buy_volume += buy_price * level_amount;
+            amount = total_amount;
+            plans[book].take(order, level_amount);
+        }
+        plans
+            .into_iter()
+            .map(|plan| if plan.levels.is_empty() { None } else { Some(plan) })
+            .collect()
+    }
+
+    /// A single order which takes every level of the plan.
+    pub fn order(&self) -> Order {
+        Order {
+            trading_pair: self.levels[0].trading_pair.clone(),
+            price: decimal::to_f64(self.worst_price),
+            amount: decimal::to_f64(self.amount),
+        }
+    }
+
+    fn empty() -> ExecutionPlan {
+        ExecutionPlan {
+            levels: Vec::new(),
+            amount: Decimal::ZERO,
+            average_price: Decimal::ZERO,
+            worst_price: Decimal::ZERO,
+        }
+    }
+
+    fn take(&mut self, order: &Order, amount: Amount) {
+        let price = decimal::from_f64(order.price);
+        let volume = self.average_price * self.amount + price * amount;
+        self.amount += amount;
+        self.average_price = volume / self.amount;
+        self.worst_price = price;
+        self.levels.push(Order {
+            trading_pair: order.trading_pair.clone(),
+            price: order.price,
+            amount: decimal::to_f64(amount),
+        });
     }
 }
 
 pub enum FindError {
     NoProfit,
-    EmptyStock,
-    SnifferError(String),
-    AccountantError((Coin, String)),
+    Exchange(Error),
 }
 
 impl std::fmt::Display for FindError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             FindError::NoProfit => write!(f, "No Profit"),
-            FindError::EmptyStock => write!(f, "Empty Stock"),
-            FindError::SnifferError(error) => write!(f, "{}", error),
-            FindError::AccountantError((_coin, error)) => write!(f, "{}", error),
+            FindError::Exchange(error) => write!(f, "{}", error),
         }
     }
 }
 
-async fn find_the_best_order<'a>(
+async fn find_the_best_orders<'a>(
     entry: &Entry,
     merchants: &[&'a dyn Merchant],
     pair: TradingPair,
     amount_calculator: &AmountCalculator,
     low_amount_filter: &LowAmountFilter,
-) -> Result<(Order, &'a dyn Merchant), FindError> {
-    let mut result = None;
-    let mut the_best_merchant = None;
-    for merchant in merchants.iter() {
-        let sniffer = merchant.sniffer();
-        let orders = match sniffer.all_the_best_orders(pair.clone(), 15).await {
-            Ok(orders) => orders,
-            Err(error) => {
-                return Err(FindError::SnifferError(error));
-            }
-        };
-        let orders = low_amount_filter.filter(orders);
-        let the_best_order = match orders.get(0) {
-            Some(order) => order,
-            None => {
-                return Err(FindError::EmptyStock);
+    fee_model: &FeeModel,
+    min_profit: Decimal,
+    fan_out: &FanOut,
+) -> Result<Vec<(ExecutionPlan, &'a dyn Merchant)>, FindError> {
+    let quotes = fan_out
+        .join(merchants.iter().map(|merchant| {
+            let sniffer = merchant.sniffer();
+            let accountant = merchant.accountant();
+            let pair = pair.clone();
+            async move {
+                futures::future::join(
+                    fan_out.call(sniffer.all_the_best_orders(pair.clone(), 15)),
+                    fan_out.call(accountant.ask(pair.coin_to_spend())),
+                )
+                .await
             }
-        };
-        let accountant = merchant.accountant();
-        let currency = match accountant.ask(pair.coin_to_spend()).await {
-            Ok(currency) => currency,
+        }))
+        .await;
+    // A merchant without a usable book is left out of the route.
+    let mut books = Vec::with_capacity(merchants.len());
+    let mut failure = None;
+    for (merchant, (orders, currency)) in merchants.iter().zip(quotes.into_iter()) {
+        let quote = orders
+            .map(|orders| low_amount_filter.filter(orders))
+            .map_err(|error| Error::sniffer(merchant.id(), pair.clone(), error))
+            .and_then(|orders| {
+                if orders.is_empty() {
+                    let error = "Empty stock".to_owned();
+                    Err(Error::sniffer(merchant.id(), pair.clone(), error))
+                } else {
+                    Ok(orders)
+                }
+            })
+            .and_then(|orders| match currency {
+                Ok(currency) => Ok((orders, currency)),
+                Err(error) => Err(Error::accountant(merchant.id(), pair.clone(), error)),
+            });
+        let (orders, currency) = match quote {
+            Ok(quote) => quote,
             Err(error) => {
-                return Err(FindError::AccountantError((pair.coin_to_spend(), error)));
+                log::warn!("{} is left out of the route: {}", merchant.id(), error);
+                failure = failure.or(Some(error));
+                continue;
             }
         };
-        let currency_to_spend = agnostic::price::convert_to_base_coin_amount(
-            pair.target.clone(),
-            pair.side.clone(),
-            &the_best_order.price.into(),
-            currency.amount,
-        );
-        let balance = Balance {
-            amount: currency_to_spend,
-            fee: amount_calculator.fee,
+        // The entries are assumed to come from the limit orders on the same merchant.
+        let fees = fee_model.fees(merchant.id());
+        let profit_calculator = match pair.side {
+            Side::Sell => ProfitCalculator::new(fees.taker, fees.maker),
+            Side::Buy => ProfitCalculator::new(fees.maker, fees.taker),
         };
-        let amount = match amount_calculator
-            .evaluate(the_best_order.amount.min(entry.amount), &balance)
-        {
-            Some(amount) => amount.value(),
-            None => continue,
-        };
-        match (pair.side.clone(), &mut result) {
-            (_, None) => {
-                result = Some(Order {
-                    trading_pair: pair.clone(),
-                    price: the_best_order.price,
-                    amount,
-                });
-                the_best_merchant = Some(merchant);
-            }
-            (Side::Sell, Some(order)) => {
-                if the_best_order.price > order.price {
-                    order.price = the_best_order.price;
-                    order.amount = amount;
-                    the_best_merchant = Some(merchant);
-                }
-            }
-            (Side::Buy, Some(order)) => {
-                if the_best_order.price < order.price {
-                    order.price = the_best_order.price;
-                    order.amount = amount;
-                    the_best_merchant = Some(merchant);
-                }
-            }
-        }
+        let balance = amount_calculator
+            .balance(decimal::from_f64(currency.amount), fees.get(pair.target));
+        books.push((*merchant, orders, balance, profit_calculator));
+    }
+    if books.is_empty() {
+        return Err(failure.map_or(FindError::NoProfit, FindError::Exchange));
     }
-    match (result, the_best_merchant) {
-        (Some(order), Some(merchant)) => Ok((order, *merchant)),
-        _ => Err(FindError::NoProfit),
+    let plans = ExecutionPlan::route(
+        entry,
+        &books
+            .iter()
+            .map(|(_merchant, orders, balance, profit_calculator)| Book {
+                orders,
+                balance,
+                profit_calculator: *profit_calculator,
+            })
+            .collect::<Vec<_>>(),
+        min_profit,
+    );
+    let plans: Vec<(ExecutionPlan, &'a dyn Merchant)> = plans
+        .into_iter()
+        .zip(books.iter().map(|(merchant, ..)| merchant))
+        .filter_map(|(plan, merchant)| match plan {
+            Some(plan) if plan.amount >= amount_calculator.min_amount_threshold => {
+                log::debug!(
+                    "{}: Levels {:^4} Amount {:^10.3} VWAP {:^10.3}",
+                    merchant.id(),
+                    plan.levels.len(),
+                    plan.amount,
+                    plan.average_price);
+                Some((plan, *merchant))
+            }
+            _ => None,
+        })
+        .collect();
+    if plans.is_empty() {
+        Err(FindError::NoProfit)
+    } else {
+        Ok(plans)
     }
 }
@@ -1,45 +1,125 @@
+//! Reseller Saver
+//!
+//! Storages are written to a temporary file which replaces the saved one, so a crash leaves
+//! either the previous or the new storages on the disk. The replaced files are kept as
+//! `<file>.1`, `<file>.2`, ... up to the configured number of backups.
+use crate::atomic_file;
 use crate::bookkeeper::Coins;
+use crate::error::Error;
 use crate::reseller::{Entry, Reseller, Storage};
 use std::collections::HashMap;
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::convert::TryFrom;
 use std::path::PathBuf;
 use agnostic::trading_pair;
 
+#[derive(serde::Serialize)]
+struct VersionedStorages<'a> {
+    version: u32,
+    storages: [HashMap<Coins, &'a Vec<Entry>>; 2],
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum SavedStorages {
+    Versioned {
+        version: u32,
+        storages: [HashMap<Coins, Vec<Entry>>; 2],
+    },
+    /// The first format had no version.
+    Legacy([HashMap<Coins, Vec<Entry>>; 2]),
+}
+
 pub struct ResellerSaver {
-    file: std::fs::File,
+    path: PathBuf,
+    backups: usize,
+    /// Entries of the coins which are unknown to agnostic. They are kept as is.
+    unsupported: [HashMap<Coins, Vec<Entry>>; 2],
 }
 
 impl ResellerSaver {
-    pub fn save_storages(&mut self, reseller: &Reseller) -> Result<(), std::io::Error> {
-        let storages = [
+    const VERSION: u32 = 2;
+    const DEFAULT_BACKUPS: usize = 3;
+
+    pub fn save_storages(&mut self, reseller: &Reseller) -> Result<(), Error> {
+        let mut storages = [
             convert_storage(&reseller.buy_storage),
             convert_storage(&reseller.sell_storage),
         ];
-        self.file.seek(SeekFrom::Start(0))?;
-        self.file.set_len(0)?;
-        self.file.write_all(&serde_json::to_vec(&storages)?)
+        for (storage, unsupported) in storages.iter_mut().zip(self.unsupported.iter()) {
+            unsupported.iter().for_each(|(key, value)| {
+                storage.insert(key.clone(), value);
+            });
+        }
+        let storages = VersionedStorages {
+            version: Self::VERSION,
+            storages,
+        };
+        let storages = serde_json::to_vec(&storages)?;
+        self.rotate_backups()?;
+        Ok(atomic_file::write(&self.path, &storages)?)
+    }
+
+    pub fn load(file: impl Into<PathBuf>) -> Result<ResellerSaver, Error> {
+        Ok(ResellerSaver {
+            path: file.into(),
+            backups: Self::DEFAULT_BACKUPS,
+            unsupported: Default::default(),
+        })
     }
 
-    pub fn load(file: impl Into<PathBuf>) -> Result<ResellerSaver, std::io::Error> {
-        let file = std::fs::OpenOptions::new()
-            .write(true)
-            .read(true)
-            .create(true)
-            .open(&file.into())?;
-        Ok(ResellerSaver { file })
+    pub fn with_backups(mut self, backups: usize) -> Self {
+        self.backups = backups;
+        self
     }
 
+    /// Missing or empty file has empty storages.
     pub fn read_buy_and_sell_storages(
         &mut self,
-    ) -> Result<(Storage, Storage), std::io::Error> {
-        self.file.seek(SeekFrom::Start(0))?;
-        let mut storages = String::with_capacity(100);
-        self.file.read_to_string(&mut storages)?;
-        let storages: [HashMap<Coins, Vec<Entry>>; 2] = serde_json::from_str(&storages)?;
-        Ok((
-            to_storage(storages.get(0).unwrap()),
-            to_storage(storages.get(1).unwrap()),
-        ))
+    ) -> Result<(Storage, Storage), Error> {
+        let storages = match std::fs::read_to_string(&self.path) {
+            Ok(storages) => storages,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error.into()),
+        };
+        if storages.trim().is_empty() {
+            self.unsupported = Default::default();
+            return Ok((Storage::new(), Storage::new()));
+        }
+        let saved = serde_json::from_str::<SavedStorages>(&storages)?;
+        let [buy_storage, sell_storage] = match saved {
+            SavedStorages::Versioned { version, storages } if version <= Self::VERSION => {
+                storages
+            }
+            SavedStorages::Versioned { version, .. } => {
+                return Err(Error::persistence(format!(
+                    "Unsupported storages version {}",
+                    version
+                )));
+            }
+            SavedStorages::Legacy(storages) => storages,
+        };
+        let (buy_storage, buy_unsupported) = to_storage(buy_storage);
+        let (sell_storage, sell_unsupported) = to_storage(sell_storage);
+        self.unsupported = [buy_unsupported, sell_unsupported];
+        Ok((buy_storage, sell_storage))
+    }
+
+    pub fn backup_path(&self, index: usize) -> PathBuf {
+        atomic_file::sibling(&self.path, &format!(".{}", index))
+    }
+
+    /// The current file is copied, so it stays in place until it is replaced.
+    fn rotate_backups(&self) -> Result<(), std::io::Error> {
+        if self.backups == 0 || !self.path.exists() {
+            return Ok(());
+        }
+        for index in (1..self.backups).rev() {
+            let backup = self.backup_path(index);
+            if backup.exists() {
+                std::fs::rename(&backup, self.backup_path(index + 1))?;
+            }
+        }
+        std::fs::copy(&self.path, self.backup_path(1)).map(|_bytes| ())
     }
 }
 
@@ -50,14 +130,21 @@ fn convert_storage(storage: &Storage) -> HashMap<Coins, &Vec<Entry>> {
         .collect()
 }
 
-fn to_storage(storage: &HashMap<Coins, Vec<Entry>>) -> Storage {
+fn to_storage(storage: HashMap<Coins, Vec<Entry>>) -> (Storage, HashMap<Coins, Vec<Entry>>) {
     let mut map: Storage = HashMap::new();
+    let mut unsupported = HashMap::new();
     storage
-        .iter()
+        .into_iter()
         .for_each(|(key, value)| {
-            map.insert(
-                trading_pair::Coins::from(key.clone()),
-                value.clone());
+            match trading_pair::Coins::try_from(key.clone()) {
+                Ok(coins) => {
+                    map.insert(coins, value);
+                }
+                Err(error) => {
+                    log::warn!("{}", error);
+                    unsupported.insert(key, value);
+                }
+            }
         });
-    map
+    (map, unsupported)
 }
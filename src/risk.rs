@@ -0,0 +1,559 @@
+//! Risk
+//!
+//! Every order of the strategies passes the `RiskManager` right before it is sent to the
+//! trader. The positions and the daily loss come from the `Bookkeeper` ledger and are
+//! refreshed with `RiskManager::sync`, the rate of the orders is counted by the manager
+//! itself. The position limits also count my resting limit orders, given to
+//! `RiskManager::sync_orders`, and the orders passed since the last sync, as if all the
+//! orders of the same direction were filled. Cancellations only reduce the risk and are
+//! never checked.
+use crate::bookkeeper::{self, Bookkeeper, TimeWindow, TradingResult};
+use crate::decimal::{self, Decimal};
+use crate::error::Error;
+use crate::limit_master::MerchantId;
+use agnostic::order::{Order, OrderWithId};
+use agnostic::trading_pair::{Side, TradingPair};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Rejection {
+    NotionalAboveLimit {
+        notional: Decimal,
+        max_notional: Decimal,
+    },
+    PositionAboveLimit {
+        coin: String,
+        position: Decimal,
+        max_position: Decimal,
+    },
+    DailyLossAboveLimit {
+        loss: Decimal,
+        max_daily_loss: Decimal,
+    },
+    OrderRateAboveLimit {
+        orders: usize,
+        max_orders_per_minute: usize,
+    },
+    PriceOutOfBand {
+        price: Decimal,
+        best_price: Decimal,
+        max_deviation: Decimal,
+    },
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejection::NotionalAboveLimit {
+                notional,
+                max_notional,
+            } => write!(f, "Notional {} is above the limit {}", notional, max_notional),
+            Rejection::PositionAboveLimit {
+                coin,
+                position,
+                max_position,
+            } => write!(
+                f,
+                "Position {} of {} is above the limit {}",
+                position, coin, max_position
+            ),
+            Rejection::DailyLossAboveLimit {
+                loss,
+                max_daily_loss,
+            } => write!(f, "Daily loss {} reached the limit {}", loss, max_daily_loss),
+            Rejection::OrderRateAboveLimit {
+                orders,
+                max_orders_per_minute,
+            } => write!(
+                f,
+                "{} orders of the last minute reached the limit {}",
+                orders, max_orders_per_minute
+            ),
+            Rejection::PriceOutOfBand {
+                price,
+                best_price,
+                max_deviation,
+            } => write!(
+                f,
+                "Price {} is more than {} away from the best price {}",
+                price, max_deviation, best_price
+            ),
+        }
+    }
+}
+
+/// `None` disables the corresponding check.
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct RiskLimits {
+    /// Max price * amount of an order.
+    pub max_notional: Option<Decimal>,
+    /// Max absolute position per coin, e.g. `TON`. The coins without a limit are free.
+    pub max_positions: HashMap<String, Decimal>,
+    /// Max loss of the last 24 hours after fees. Once it is reached every order is
+    /// rejected.
+    pub max_daily_loss: Option<Decimal>,
+    /// Max orders sent to a single merchant within a minute.
+    pub max_orders_per_minute: Option<usize>,
+    /// Max relative distance of the price from the current best order.
+    pub max_price_deviation: Option<Decimal>,
+}
+
+impl RiskLimits {
+    pub fn with_max_position(mut self, coin: &str, max_position: Decimal) -> Self {
+        self.max_positions.insert(coin.to_owned(), max_position);
+        self
+    }
+}
+
+/// Position changes of the orders which may still be filled, split by direction.
+#[derive(Default, Clone, Copy, Debug)]
+struct Exposure {
+    long: Decimal,
+    short: Decimal,
+}
+
+impl Exposure {
+    fn add(&mut self, change: Decimal) {
+        if change > Decimal::ZERO {
+            self.long += change;
+        } else {
+            self.short += change;
+        }
+    }
+
+    /// The change if every order of the direction of `change` is filled.
+    fn towards(&self, change: Decimal) -> Decimal {
+        if change > Decimal::ZERO {
+            self.long
+        } else {
+            self.short
+        }
+    }
+}
+
+#[derive(Default, Clone, Debug)]
+struct State {
+    /// Signed positions per coin.
+    positions: HashMap<String, Decimal>,
+    /// My resting limit orders by the last `sync_orders`.
+    resting: HashMap<String, Exposure>,
+    /// The orders passed since the last sync.
+    pending: HashMap<String, Exposure>,
+    daily_pnl: Decimal,
+    /// Times of the orders per merchant.
+    sent: HashMap<String, VecDeque<Instant>>,
+}
+
+/// Cheap to clone, the clones share the positions and the order rate, so a single manager
+/// may be given to all the strategies.
+#[derive(Default, Clone, Debug)]
+pub struct RiskManager {
+    limits: RiskLimits,
+    /// Fee of the ledger.
+    fee: Decimal,
+    state: Arc<Mutex<State>>,
+}
+
+impl RiskManager {
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    pub fn new(limits: RiskLimits) -> RiskManager {
+        RiskManager {
+            limits,
+            ..RiskManager::default()
+        }
+    }
+
+    pub fn with_fee(mut self, fee: Decimal) -> Self {
+        self.fee = fee;
+        self
+    }
+
+    pub fn limits(&self) -> &RiskLimits {
+        &self.limits
+    }
+
+    /// Reads the positions and the daily PnL from the ledger.
+    pub fn sync(&self, bookkeeper: &mut Bookkeeper) -> Result<(), Error> {
+        let trades = bookkeeper.get_all_trades()?;
+        let window = TimeWindow::last(Self::DAY);
+        // Today's trades are applied on top of the positions carried over from the
+        // previous days, so closing them counts towards the daily loss.
+        let mut result = TradingResult::default();
+        let mut daily_pnl = Decimal::ZERO;
+        for trade in trades.iter() {
+            let pair = result.pairs.entry(trade.coins.clone()).or_default();
+            let before = pair.net();
+            pair.apply(&trade.side, trade.price, trade.amount, self.fee);
+            if window.contains(trade) {
+                daily_pnl += pair.net() - before;
+            }
+        }
+        let mut positions: HashMap<String, Decimal> = HashMap::new();
+        for (coins, result) in result.pairs.into_iter() {
+            *positions.entry(coins.base).or_default() += result.base_position;
+            *positions.entry(coins.quote).or_default() += result.quote_position;
+        }
+        let mut state = self.lock();
+        state.positions = positions;
+        state.pending.clear();
+        state.daily_pnl = daily_pnl;
+        Ok(())
+    }
+
+    /// Replaces the resting limit orders, is called along with `sync` since the orders
+    /// passed before it are forgotten by it.
+    pub fn sync_orders<'o>(&self, orders: impl IntoIterator<Item = &'o OrderWithId>) {
+        let mut resting: HashMap<String, Exposure> = HashMap::new();
+        for order in orders {
+            let price = decimal::from_f64(order.price);
+            let amount = decimal::from_f64(order.amount);
+            for (coin, change) in changes(&order.trading_pair, price, amount).iter() {
+                resting.entry(coin.clone()).or_default().add(*change);
+            }
+        }
+        self.lock().resting = resting;
+    }
+
+    /// Signed position of `coin` by the last sync.
+    pub fn position(&self, coin: &str) -> Decimal {
+        self.lock().positions.get(coin).copied().unwrap_or_default()
+    }
+
+    /// Counts the order towards the rate limit once it passes. `best_price` is the price
+    /// of the best order on the other side of the book, the price band is not checked
+    /// without it.
+    pub fn check(
+        &self,
+        merchant_id: MerchantId,
+        order: &Order,
+        best_price: Option<Decimal>,
+    ) -> Result<(), Error> {
+        self.check_all(&[(merchant_id, order, best_price)])
+    }
+
+    /// Checks the orders which are sent together, each one along with the ones before it.
+    /// None of them is counted unless all of them pass.
+    pub fn check_all(
+        &self,
+        orders: &[(MerchantId, &Order, Option<Decimal>)],
+    ) -> Result<(), Error> {
+        let mut state = self.lock();
+        let mut checked = state.clone();
+        for &(merchant_id, order, best_price) in orders.iter() {
+            let rejection = self.rejection(&mut checked, merchant_id, order, best_price);
+            if let Some(rejection) = rejection {
+                log::warn!("Rejected {:?} on {}: {}", order, merchant_id, rejection);
+                let trading_pair = order.trading_pair.clone();
+                return Err(Error::risk(merchant_id, trading_pair, rejection));
+            }
+            let price = decimal::from_f64(order.price);
+            let amount = decimal::from_f64(order.amount);
+            for (coin, change) in changes(&order.trading_pair, price, amount).iter() {
+                checked.pending.entry(coin.clone()).or_default().add(*change);
+            }
+        }
+        *state = checked;
+        Ok(())
+    }
+
+    fn rejection(
+        &self,
+        state: &mut State,
+        merchant_id: MerchantId,
+        order: &Order,
+        best_price: Option<Decimal>,
+    ) -> Option<Rejection> {
+        let price = decimal::from_f64(order.price);
+        let amount = decimal::from_f64(order.amount);
+        if let Some(max_daily_loss) = self.limits.max_daily_loss {
+            let loss = -state.daily_pnl;
+            if loss >= max_daily_loss {
+                return Some(Rejection::DailyLossAboveLimit {
+                    loss,
+                    max_daily_loss,
+                });
+            }
+        }
+        let notional = price * amount;
+        if let Some(max_notional) = self.limits.max_notional {
+            if notional > max_notional {
+                return Some(Rejection::NotionalAboveLimit {
+                    notional,
+                    max_notional,
+                });
+            }
+        }
+        for (coin, change) in changes(&order.trading_pair, price, amount).iter() {
+            let max_position = match self.limits.max_positions.get(coin) {
+                Some(max_position) => *max_position,
+                None => continue,
+            };
+            let exposure = |exposures: &HashMap<String, Exposure>| {
+                exposures
+                    .get(coin)
+                    .map_or(Decimal::ZERO, |exposure| exposure.towards(*change))
+            };
+            let current = state.positions.get(coin).copied().unwrap_or_default()
+                + exposure(&state.resting)
+                + exposure(&state.pending);
+            let position = current + change;
+            // The orders which reduce the position are let through.
+            if position.abs() > max_position && position.abs() > current.abs() {
+                return Some(Rejection::PositionAboveLimit {
+                    coin: coin.clone(),
+                    position,
+                    max_position,
+                });
+            }
+        }
+        if let (Some(max_deviation), Some(best_price)) =
+            (self.limits.max_price_deviation, best_price)
+        {
+            if best_price > Decimal::ZERO
+                && ((price - best_price) / best_price).abs() > max_deviation
+            {
+                return Some(Rejection::PriceOutOfBand {
+                    price,
+                    best_price,
+                    max_deviation,
+                });
+            }
+        }
+        if let Some(max_orders_per_minute) = self.limits.max_orders_per_minute {
+            let now = Instant::now();
+            let sent = state.sent.entry(merchant_id.to_owned()).or_default();
+            while sent.front().map_or(false, |time| now - *time >= Self::MINUTE) {
+                sent.pop_front();
+            }
+            if sent.len() >= max_orders_per_minute {
+                return Some(Rejection::OrderRateAboveLimit {
+                    orders: sent.len(),
+                    max_orders_per_minute,
+                });
+            }
+            sent.push_back(now);
+        }
+        None
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("Risk state is poisoned")
+    }
+}
+
+/// Signed changes of the base and the quote positions once the order is filled.
+fn changes(
+    trading_pair: &TradingPair,
+    price: Decimal,
+    amount: Decimal,
+) -> [(String, Decimal); 2] {
+    let coins = bookkeeper::Coins::from(trading_pair.coins);
+    let base_change = match trading_pair.side {
+        Side::Buy => amount,
+        Side::Sell => -amount,
+    };
+    [(coins.base, base_change), (coins.quote, -base_change * price)]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use agnostic::trading_pair::{Coins, Target};
+    use rust_decimal_macros::dec;
+
+    fn order(side: Side, price: f64, amount: f64) -> Order {
+        Order {
+            trading_pair: TradingPair {
+                coins: Coins::TonUsdt,
+                side,
+                target: Target::Limit,
+            },
+            price,
+            amount,
+        }
+    }
+
+    fn rejection(manager: &RiskManager, order: Order, best_price: Decimal) -> Rejection {
+        match manager.check("Test", &order, Some(best_price)) {
+            Err(Error::Risk { rejection, .. }) => rejection,
+            result => panic!("Order is not rejected: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn limits() {
+        let manager = RiskManager::new(
+            RiskLimits {
+                max_notional: Some(dec!(100)),
+                max_price_deviation: Some(dec!(0.1)),
+                max_orders_per_minute: Some(2),
+                ..RiskLimits::default()
+            }
+            .with_max_position("TON", dec!(50)),
+        );
+        assert!(matches!(
+            rejection(&manager, order(Side::Buy, 1.0, 101.0), dec!(1)),
+            Rejection::NotionalAboveLimit { .. }
+        ));
+        assert!(matches!(
+            rejection(&manager, order(Side::Buy, 1.0, 60.0), dec!(1)),
+            Rejection::PositionAboveLimit { .. }
+        ));
+        assert!(matches!(
+            rejection(&manager, order(Side::Sell, 1.2, 10.0), dec!(1)),
+            Rejection::PriceOutOfBand { .. }
+        ));
+        let close_order = order(Side::Sell, 1.05, 10.0);
+        assert!(manager.check("Test", &close_order, Some(dec!(1))).is_ok());
+        assert!(manager.check("Test", &order(Side::Buy, 0.95, 10.0), None).is_ok());
+        let error = manager
+            .check("Test", &order(Side::Buy, 0.95, 10.0), None)
+            .unwrap_err();
+        assert!(error.is_transient());
+        assert!(matches!(
+            error,
+            Error::Risk {
+                rejection: Rejection::OrderRateAboveLimit { orders: 2, .. },
+                ..
+            }
+        ));
+        // The rate is counted per merchant.
+        assert!(manager.check("Other", &order(Side::Buy, 0.95, 10.0), None).is_ok());
+        assert!(RiskManager::default()
+            .check("Test", &order(Side::Buy, 100.0, 1000.0), Some(dec!(1)))
+            .is_ok());
+    }
+
+    #[test]
+    fn check_all_or_nothing() {
+        let manager = RiskManager::new(
+            RiskLimits {
+                max_orders_per_minute: Some(1),
+                ..RiskLimits::default()
+            }
+            .with_max_position("TON", dec!(50)),
+        );
+        let buy = order(Side::Buy, 1.0, 40.0);
+        let error = manager
+            .check_all(&[("Test", &buy, None), ("Other", &buy, None)])
+            .unwrap_err();
+        assert_eq!(error.merchant_id(), Some("Other"));
+        assert_eq!(manager.lock().pending.len(), 0);
+        // Neither the rate nor the exposure of the first order is counted.
+        assert!(manager.check("Test", &buy, None).is_ok());
+    }
+
+    #[test]
+    fn exposure_of_pending_and_resting_orders() {
+        let limits = RiskLimits::default().with_max_position("TON", dec!(50));
+        let manager = RiskManager::new(limits);
+        let order_to_rest = order(Side::Buy, 1.0, 30.0);
+        let resting = OrderWithId {
+            id: "1".to_owned(),
+            trading_pair: order_to_rest.trading_pair,
+            price: order_to_rest.price,
+            amount: order_to_rest.amount,
+        };
+        manager.sync_orders(std::iter::once(&resting));
+        assert!(matches!(
+            rejection(&manager, order(Side::Buy, 1.0, 30.0), dec!(1)),
+            Rejection::PositionAboveLimit { .. }
+        ));
+        // The resting buy may be cancelled, so it does not offset a sell.
+        assert!(manager.check("Test", &order(Side::Sell, 1.0, 40.0), None).is_ok());
+        assert!(manager.check("Test", &order(Side::Buy, 1.0, 15.0), None).is_ok());
+        assert!(matches!(
+            rejection(&manager, order(Side::Buy, 1.0, 10.0), dec!(1)),
+            Rejection::PositionAboveLimit { .. }
+        ));
+
+        let path = std::env::temp_dir().join("open_midas_risk_exposure.agnostic");
+        let _ = std::fs::remove_file(&path);
+        let mut bookkeeper = Bookkeeper::open(path.clone()).unwrap();
+        manager.sync(&mut bookkeeper).unwrap();
+        manager.sync_orders(std::iter::empty());
+        assert!(manager.check("Test", &order(Side::Buy, 1.0, 50.0), None).is_ok());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn sync_with_ledger() {
+        let path = std::env::temp_dir().join("open_midas_risk_sync.agnostic");
+        let _ = std::fs::remove_file(&path);
+        let mut bookkeeper = Bookkeeper::open(path.clone()).unwrap();
+        let coins = bookkeeper::Coins::new("TON", "USDT");
+        let trade = |side, price| bookkeeper::Trade {
+            id: "1".to_owned(),
+            coins: coins.clone(),
+            side,
+            target: bookkeeper::Target::Market,
+            price,
+            amount: dec!(40),
+            time: Some(bookkeeper::now_millis()),
+            merchant_id: None,
+            strategy: None,
+        };
+        bookkeeper.append(&trade(bookkeeper::Side::Buy, dec!(1))).unwrap();
+        bookkeeper.append(&trade(bookkeeper::Side::Sell, dec!(0.5))).unwrap();
+        bookkeeper.append(&trade(bookkeeper::Side::Buy, dec!(0.5))).unwrap();
+        let manager = RiskManager::new(
+            RiskLimits {
+                max_daily_loss: Some(dec!(30)),
+                ..RiskLimits::default()
+            }
+            .with_max_position("TON", dec!(50)),
+        );
+        manager.sync(&mut bookkeeper).unwrap();
+        assert_eq!(manager.position("TON"), dec!(40));
+        assert_eq!(manager.position("USDT"), dec!(-40));
+        assert!(manager.check("Test", &order(Side::Buy, 0.5, 10.0), None).is_ok());
+
+        let manager = RiskManager::new(RiskLimits {
+            max_daily_loss: Some(dec!(20)),
+            ..RiskLimits::default()
+        });
+        manager.sync(&mut bookkeeper).unwrap();
+        assert!(matches!(
+            rejection(&manager, order(Side::Sell, 0.5, 1.0), dec!(0.5)),
+            Rejection::DailyLossAboveLimit { .. }
+        ));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn daily_loss_closes_yesterday_position() {
+        let path = std::env::temp_dir().join("open_midas_risk_yesterday.agnostic");
+        let _ = std::fs::remove_file(&path);
+        let mut bookkeeper = Bookkeeper::open(path.clone()).unwrap();
+        let now = bookkeeper::now_millis();
+        let trade = |side, price, time| bookkeeper::Trade {
+            id: "1".to_owned(),
+            coins: bookkeeper::Coins::new("TON", "USDT"),
+            side,
+            target: bookkeeper::Target::Market,
+            price,
+            amount: dec!(40),
+            time: Some(time),
+            merchant_id: None,
+            strategy: None,
+        };
+        let yesterday = now - RiskManager::DAY.as_millis() as i64 - 1;
+        bookkeeper.append(&trade(bookkeeper::Side::Buy, dec!(1), yesterday)).unwrap();
+        bookkeeper.append(&trade(bookkeeper::Side::Sell, dec!(0.5), now)).unwrap();
+        let manager = RiskManager::new(RiskLimits {
+            max_daily_loss: Some(dec!(20)),
+            ..RiskLimits::default()
+        });
+        manager.sync(&mut bookkeeper).unwrap();
+        assert_eq!(manager.position("TON"), dec!(0));
+        assert!(matches!(
+            rejection(&manager, order(Side::Sell, 0.5, 1.0), dec!(0.5)),
+            Rejection::DailyLossAboveLimit { .. }
+        ));
+        let _ = std::fs::remove_file(&path);
+    }
+}
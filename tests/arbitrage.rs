@@ -0,0 +1,106 @@
+use agnostic::merchant;
+use agnostic::trading_pair::{Coins, Side};
+use agnostic_test::merchant::Merchant;
+use agnostic_test::sniffer::{SnifferBuilder, StockGenerator};
+use open_midas::arbitrage::Arbitrage;
+use open_midas::bookkeeper::{self, Bookkeeper, Strategy};
+use open_midas::calculators::AmountCalculator;
+use open_midas::instrument::{InstrumentSpec, Instruments};
+use rust_decimal_macros::dec;
+use std::sync::Arc;
+use tokio_test::block_on;
+
+fn merchant_with_stock(id: &'static str, bid: f64, ask: f64) -> Merchant {
+    Merchant::with_sniffer(
+        id,
+        Arc::new(
+            SnifferBuilder::new()
+                .buy_stock_generator(StockGenerator::new(Side::Buy, bid, 0.1, 10))
+                .sell_stock_generator(StockGenerator::new(Side::Sell, ask, 0.1, 10))
+                .build(100f64),
+        ),
+    )
+}
+
+fn default_arbitrage<'a>(merchants: Vec<&'a dyn merchant::Merchant>) -> Arbitrage<'a> {
+    Arbitrage::new(
+        merchants,
+        Coins::TonUsdt,
+        AmountCalculator {
+            min_amount_threshold: dec!(0.1),
+            fee: dec!(0.01),
+        },
+        dec!(0.01),
+    )
+}
+
+#[test]
+fn arbitrage_without_crossed_books() {
+    let first = merchant_with_stock("First", 0.9, 1.0);
+    let second = merchant_with_stock("Second", 0.95, 1.05);
+    let merchants: Vec<&dyn merchant::Merchant> = vec![&first, &second];
+    let arbitrage = default_arbitrage(merchants);
+    assert_eq!(block_on(arbitrage.find_opportunity()), None);
+}
+
+#[test]
+fn arbitrage_with_crossed_books() {
+    let path = std::env::temp_dir().join("open_midas_arbitrage_with_crossed_books.txt");
+    let _ = std::fs::remove_file(&path);
+    let mut bookkeeper = Bookkeeper::open(path.clone()).expect("Failed to open bookkeeper");
+    let first = merchant_with_stock("First", 0.9, 1.0);
+    let second = merchant_with_stock("Second", 1.2, 1.3);
+    let merchants: Vec<&dyn merchant::Merchant> = vec![&first, &second];
+    let arbitrage = default_arbitrage(merchants);
+
+    let opportunity = block_on(arbitrage.find_opportunity()).expect("No opportunity");
+    assert_eq!(opportunity.buy.merchant_id, "First");
+    assert_eq!(opportunity.buy.order.price, 1.0);
+    assert_eq!(opportunity.sell.merchant_id, "Second");
+    assert_eq!(opportunity.sell.order.price, 1.2);
+    assert!(opportunity.profit > dec!(0.1), "{:#?}", opportunity);
+
+    let legs = block_on(arbitrage.iterate(&mut bookkeeper))
+        .expect("Failed to iterate")
+        .expect("No legs");
+    assert_eq!(legs.buy.merchant_id, "First");
+    assert_eq!(legs.sell.merchant_id, "Second");
+    let trades = bookkeeper.get_all_trades().expect("Failed to read trades");
+    assert_eq!(trades.len(), 2);
+    assert!(trades
+        .iter()
+        .all(|trade| trade.strategy == Some(Strategy::Arbitrage)));
+    assert_eq!(trades[0].side, bookkeeper::Side::Buy);
+    assert_eq!(trades[0].merchant_id.as_deref(), Some("First"));
+    assert_eq!(trades[1].side, bookkeeper::Side::Sell);
+    assert_eq!(trades[1].merchant_id.as_deref(), Some("Second"));
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn arbitrage_legs_trade_the_same_amount() {
+    let path = std::env::temp_dir().join("open_midas_arbitrage_same_amount.txt");
+    let _ = std::fs::remove_file(&path);
+    let mut bookkeeper = Bookkeeper::open(path.clone()).expect("Failed to open bookkeeper");
+    let first = merchant_with_stock("First", 0.9, 1.0);
+    let second = merchant_with_stock("Second", 1.2, 1.3);
+    let merchants: Vec<&dyn merchant::Merchant> = vec![&first, &second];
+    let lot_step = |lot_step| InstrumentSpec {
+        lot_step,
+        ..InstrumentSpec::default()
+    };
+    let arbitrage = default_arbitrage(merchants).with_instruments(
+        Instruments::default()
+            .with_spec("First", Coins::TonUsdt, lot_step(dec!(0.3)))
+            .with_spec("Second", Coins::TonUsdt, lot_step(dec!(0.2))),
+    );
+
+    block_on(arbitrage.iterate(&mut bookkeeper))
+        .expect("Failed to iterate")
+        .expect("No legs");
+    let trades = bookkeeper.get_all_trades().expect("Failed to read trades");
+    assert_eq!(trades.len(), 2);
+    assert_eq!(trades[0].amount, trades[1].amount);
+    assert!((trades[0].amount / dec!(0.6)).fract().is_zero(), "{:#?}", trades);
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,68 @@
+use agnostic::merchant::Merchant;
+use agnostic::trading_pair::Coins;
+use open_midas::backtest::{
+    Backtest, BookSnapshot, Exchange, Level, Replay, SimulatedMerchant,
+};
+use open_midas::bookkeeper::{self, Bookkeeper, Strategy};
+use open_midas::calculators::price_calculator::PriceCalculator;
+use open_midas::calculators::AmountCalculator;
+use open_midas::decimal::Decimal;
+use open_midas::limit_master::{LimitMaster, MerchantIdManager};
+use rust_decimal_macros::dec;
+
+fn snapshot(time: i64, bid: Decimal, ask: Decimal, amount: Decimal) -> BookSnapshot {
+    BookSnapshot {
+        time,
+        coins: Coins::TonUsdt.into(),
+        bids: vec![Level { price: bid, amount }],
+        asks: vec![Level { price: ask, amount }],
+    }
+}
+
+#[test]
+fn limit_master_backtest() {
+    let path = std::env::temp_dir().join("open_midas_limit_master_backtest");
+    let replay = Replay::new(vec![
+        snapshot(1000, dec!(0.99), dec!(1.01), dec!(10)),
+        // The ask crosses my buy order, which is filled partially.
+        snapshot(2000, dec!(0.97), dec!(0.98), dec!(5)),
+        snapshot(3000, dec!(0.99), dec!(1.01), dec!(10)),
+    ]);
+    replay.save(path.with_extension("books")).expect("Failed to save replay");
+    let replay =
+        Replay::load(path.with_extension("books")).expect("Failed to load replay");
+    assert_eq!(replay.len(), 3);
+
+    let exchange = Exchange::new(Coins::TonUsdt, dec!(100), dec!(1000));
+    let simulated = SimulatedMerchant::new("simulated", exchange, replay);
+    let merchants: Vec<&dyn Merchant> = vec![&simulated];
+    let mut limit_master = LimitMaster::new(
+        Coins::TonUsdt,
+        MerchantIdManager::new(&merchants),
+        PriceCalculator { profit: dec!(0.01) },
+        AmountCalculator { min_amount_threshold: dec!(1), fee: dec!(0.01) },
+    );
+    let _ = std::fs::remove_file(path.with_extension("agnostic"));
+    let mut bookkeeper = Bookkeeper::open(path.with_extension("agnostic"))
+        .expect("Failed to open bookkeeper");
+
+    let backtest = Backtest::new(vec![&simulated]);
+    let report = tokio_test::block_on(backtest.run(&mut limit_master, &mut bookkeeper))
+        .expect("Failed to run backtest");
+    assert_eq!(report.steps, 3);
+    assert_eq!(report.mark_price, Some(dec!(1)));
+    let trades = bookkeeper.get_all_trades().expect("Failed to read trades");
+    assert!(!trades.is_empty(), "{:#?}", simulated.exchange().fills());
+    assert!(trades.iter().all(|trade| {
+        trade.strategy == Some(Strategy::LimitMaster)
+            && trade.merchant_id.as_deref() == Some("simulated")
+            && trade.time.map_or(false, |time| time >= 2000)
+    }));
+    let coins = bookkeeper::Coins::from(Coins::TonUsdt);
+    let result = report.result.get(&coins).expect("No result of the coins");
+    assert_eq!(result.bought, dec!(5));
+    assert!(report.pnl(&coins).is_some());
+
+    let _ = std::fs::remove_file(path.with_extension("books"));
+    let _ = std::fs::remove_file(path.with_extension("agnostic"));
+}
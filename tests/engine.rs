@@ -0,0 +1,161 @@
+use agnostic::merchant::Merchant;
+use agnostic::order::{Order, OrderWithId};
+use agnostic::trade::Trade;
+use agnostic::trading_pair::{Coins, Side, Target, TradingPair};
+use agnostic_test::accountant::Accountant as AccountantTest;
+use agnostic_test::merchant::Merchant as MerchantTest;
+use agnostic_test::sniffer::{SnifferBuilder, StockGenerator};
+use agnostic_test::trader::{TradesLogger, Trader as TraderTest};
+use open_midas::bookkeeper::{Bookkeeper, Strategy};
+use open_midas::calculators::price_calculator::PriceCalculator;
+use open_midas::calculators::AmountCalculator;
+use open_midas::circuit_breaker::{CircuitBreaker, Trip, TripConditions};
+use open_midas::engine::Engine;
+use open_midas::filters::LowAmountFilter;
+use open_midas::limit_master::{LimitMaster, MerchantIdManager};
+use open_midas::reseller::{Reseller, Storage};
+use open_midas::reseller_saver::ResellerSaver;
+use rust_decimal_macros::dec;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn limit_trade(side: Side, id: u32) -> Trade {
+    Trade::Limit(OrderWithId {
+        id: id.to_string(),
+        trading_pair: TradingPair {
+            coins: Coins::TonUsdt,
+            side,
+            target: Target::Limit,
+        },
+        price: 1.0,
+        amount: 100.0,
+    })
+}
+
+fn merchant(id: &'static str) -> MerchantTest {
+    let trades = (0..4)
+        .flat_map(|index| vec![
+            limit_trade(Side::Buy, 2 * index),
+            limit_trade(Side::Sell, 2 * index + 1),
+        ])
+        .collect();
+    MerchantTest::custom(
+        id,
+        Arc::new(AccountantTest::default()),
+        Arc::new(SnifferBuilder::new()
+            .buy_stock_generator(StockGenerator::new(Side::Buy, 0.5, 0.1, 10))
+            .sell_stock_generator(StockGenerator::new(Side::Sell, 1.0, 0.1, 10))
+            .build(100f64)),
+        Arc::new(TradesLogger::with_orders(TraderTest::default(), trades)))
+}
+
+fn engine<'a>(merchants: &'a [&'a dyn Merchant], name: &str) -> Engine<'a> {
+    let path = std::env::temp_dir().join(name);
+    let _ = std::fs::remove_file(path.with_extension("agnostic"));
+    let _ = std::fs::remove_file(path.with_extension("json"));
+    let limit_master = LimitMaster::new(
+        Coins::TonUsdt,
+        MerchantIdManager::new(merchants),
+        PriceCalculator { profit: dec!(0.3) },
+        AmountCalculator { min_amount_threshold: dec!(1), fee: dec!(0.01) },
+    );
+    let reseller = Reseller::new(
+        Storage::new(),
+        Storage::new(),
+        merchants.to_vec(),
+        LowAmountFilter { low_amount: 0.1 },
+        AmountCalculator { min_amount_threshold: dec!(0.1), fee: dec!(0.01) },
+        dec!(0.01),
+        false,
+    );
+    let bookkeeper = Bookkeeper::open(path.with_extension("agnostic"))
+        .expect("Failed to open bookkeeper");
+    let reseller_saver = ResellerSaver::load(path.with_extension("json"))
+        .expect("Failed to load saver")
+        .with_backups(0);
+    Engine::new(limit_master, reseller, bookkeeper, reseller_saver)
+        .with_interval(Duration::from_millis(10))
+}
+
+#[test]
+fn engine_records_and_accepts_fills() {
+    let first = merchant("first");
+    let merchants: Vec<&dyn Merchant> = vec![&first];
+    let mut engine = engine(&merchants, "open_midas_engine_records_and_accepts_fills");
+
+    let cycle = tokio_test::block_on(engine.cycle()).expect("Failed to cycle");
+    assert!(cycle.fills.is_empty());
+    assert!(!cycle.update.buy.is_empty(), "{:#?}", cycle);
+
+    // The placed orders are not among my orders of the sniffer, so they are filled.
+    let cycle = tokio_test::block_on(engine.cycle()).expect("Failed to cycle");
+    assert_eq!(cycle.fills.len(), 2, "{:#?}", cycle);
+    let trades = engine.bookkeeper().get_all_trades().expect("Failed to read trades");
+    let recorded = trades
+        .iter()
+        .filter(|trade| trade.strategy == Some(Strategy::LimitMaster))
+        .count();
+    assert_eq!(recorded, 2);
+    assert!(!engine.reseller().buy_storage.is_empty() || !cycle.resales.is_empty());
+}
+
+#[test]
+fn engine_shuts_down() {
+    let first = merchant("first");
+    let merchants: Vec<&dyn Merchant> = vec![&first];
+    let name = "open_midas_engine_shuts_down";
+    let engine = engine(&merchants, name);
+    let shutdown = engine.shutdown();
+    shutdown.request();
+    tokio_test::block_on(engine.run()).expect("Failed to run");
+    let saved = std::env::temp_dir().join(name).with_extension("json");
+    assert!(saved.exists());
+    let _ = std::fs::remove_file(saved);
+}
+
+#[test]
+fn engine_halts_until_the_breaker_is_reset() {
+    let first = merchant("first");
+    let breaker = CircuitBreaker::new(TripConditions {
+        max_trader_errors: Some(3),
+        ..TripConditions::default()
+    });
+    let guarded = breaker.wrap(&first);
+    let merchants: Vec<&dyn Merchant> = vec![&guarded];
+    let name = "open_midas_engine_halts_until_the_breaker_is_reset";
+    let mut engine = engine(&merchants, name).with_circuit_breaker(breaker.clone());
+
+    let cycle = tokio_test::block_on(engine.cycle()).expect("Failed to cycle");
+    assert_eq!(cycle.trip, None);
+    assert!(!cycle.update.buy.is_empty(), "{:#?}", cycle);
+
+    breaker.kill("Test");
+    let cycle = tokio_test::block_on(engine.cycle()).expect("Failed to cycle");
+    let trip = Trip::Manual { reason: "Test".to_owned() };
+    assert_eq!(cycle.trip, Some(trip.clone()));
+    assert!(cycle.update.buy.is_empty(), "{:#?}", cycle);
+    // The orders of the first cycle are filled before they are deleted.
+    assert_eq!(cycle.fills.len(), 2, "{:#?}", cycle);
+    let trades = engine.bookkeeper().get_all_trades().expect("Failed to read trades");
+    assert_eq!(trades.len(), 2);
+    let refused = tokio_test::block_on(guarded.trader().create_order(Order {
+        trading_pair: TradingPair {
+            coins: Coins::TonUsdt,
+            side: Side::Buy,
+            target: Target::Limit,
+        },
+        price: 1.0,
+        amount: 1.0,
+    }));
+    assert!(refused.is_err());
+    let cycle = tokio_test::block_on(engine.cycle()).expect("Failed to cycle");
+    assert_eq!(cycle.trip, Some(trip));
+
+    breaker.reset();
+    let cycle = tokio_test::block_on(engine.cycle()).expect("Failed to cycle");
+    assert_eq!(cycle.trip, None);
+    assert!(!cycle.update.buy.is_empty(), "{:#?}", cycle);
+    let path = std::env::temp_dir().join(name);
+    let _ = std::fs::remove_file(path.with_extension("agnostic"));
+    let _ = std::fs::remove_file(path.with_extension("json"));
+}
@@ -1,21 +1,41 @@
 use agnostic::{
     merchant::Merchant,
     trading_pair::{Coins, Side, Target, TradingPair},
-    order::OrderWithId,
+    order::{Order, OrderWithId},
     trade::Trade,
-    market::{Accountant, Sniffer},
+    market::{Accountant, Sniffer, Trader},
 };
 use agnostic_test::{
     merchant::Merchant as MerchantTest,
     trader::{TradesLogger, Trader as TraderTest},
-    sniffer::{Sniffer as SnifferTest, SnifferBuilder, OrderWithId as TestOrderWithId},
+    sniffer::{
+        Sniffer as SnifferTest,
+        SnifferBuilder,
+        StockGenerator,
+        OrderWithId as TestOrderWithId,
+    },
     accountant::Accountant as AccountantTest,
 };
 use open_midas::{
-    calculators::{amount_calculator::AmountCalculator, price_calculator::PriceCalculator},
-    limit_master::{LimitMaster, MerchantIdManager},
+    backtest::{Exchange, SharedExchange},
+    calculators::{
+        amount_calculator::AmountCalculator,
+        fee_model::{FeeModel, Fees},
+        inventory_skew::InventorySkew,
+        ladder::Ladder,
+        price_calculator::PriceCalculator,
+        spread_model::VolatilitySpread,
+    },
+    error::Error,
+    fan_out::FanOut,
+    instrument::{InstrumentSpec, Instruments},
+    limit_master::{LimitMaster, MerchantIdManager, OrderChange, Tolerance, Update},
+    limit_master_saver::{LimitMasterSaver, OrderSnapshot, OrdersSnapshot},
+    risk::{RiskLimits, RiskManager},
 };
+use rust_decimal_macros::dec;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 #[derive(Default)]
 pub struct LimitMasterTestContext {
@@ -47,6 +67,78 @@ impl LimitMasterTestContext {
     }
 }
 
+pub struct DelayedSniffer {
+    pub sniffer: Arc<dyn Sniffer>,
+    pub delay: Duration,
+}
+
+impl Sniffer for DelayedSniffer {
+    fn all_the_best_orders(
+        &self,
+        trading_pair: TradingPair,
+        count: u32,
+    ) -> agnostic::market::Future<Result<Vec<Order>, String>> {
+        let sniffer = self.sniffer.clone();
+        let delay = self.delay;
+        Box::pin(async move {
+            futures_timer::Delay::new(delay).await;
+            sniffer.all_the_best_orders(trading_pair, count).await
+        })
+    }
+
+    fn get_my_orders(
+        &self,
+        trading_pair: TradingPair,
+    ) -> agnostic::market::Future<Result<Vec<OrderWithId>, String>> {
+        let sniffer = self.sniffer.clone();
+        let delay = self.delay;
+        Box::pin(async move {
+            futures_timer::Delay::new(delay).await;
+            sniffer.get_my_orders(trading_pair).await
+        })
+    }
+}
+
+/// Answers with a single order of a broken price.
+pub struct NanSniffer;
+
+impl Sniffer for NanSniffer {
+    fn all_the_best_orders(
+        &self,
+        trading_pair: TradingPair,
+        _count: u32,
+    ) -> agnostic::market::Future<Result<Vec<Order>, String>> {
+        let order = Order {
+            trading_pair,
+            price: f64::NAN,
+            amount: 100f64,
+        };
+        Box::pin(futures::future::ready(Ok(vec![order])))
+    }
+
+    fn get_my_orders(
+        &self,
+        _trading_pair: TradingPair,
+    ) -> agnostic::market::Future<Result<Vec<OrderWithId>, String>> {
+        Box::pin(futures::future::ready(Ok(Vec::new())))
+    }
+}
+
+pub struct FailingTrader;
+
+impl Trader for FailingTrader {
+    fn create_order(
+        &self,
+        _order: Order,
+    ) -> agnostic::market::Future<Result<Trade, String>> {
+        Box::pin(futures::future::ready(Err("Exchange is down".to_owned())))
+    }
+
+    fn delete_order(&self, _id: &str) -> agnostic::market::Future<Result<(), String>> {
+        Box::pin(futures::future::ready(Err("Exchange is down".to_owned())))
+    }
+}
+
 fn default_buy_trading_pair() -> TradingPair {
     TradingPair {
         coins: Coins::TonUsdt,
@@ -84,11 +176,11 @@ fn default_limit_master() {
     let merchants = test_context.merchants();
     let merchants_manager = MerchantIdManager::new(&merchants);
     let price_calculator = PriceCalculator {
-        profit: 0.3f64,
+        profit: dec!(0.3),
     };
     let amount_calculator = AmountCalculator {
-        min_amount_threshold: 1f64,
-        fee: 0.01f64
+        min_amount_threshold: dec!(1),
+        fee: dec!(0.01)
     };
     let mut limit_master = LimitMaster::new(
         Coins::TonUsdt,
@@ -153,11 +245,11 @@ fn update_and_check() {
     let merchants = test_context.merchants();
     let merchants_manager = MerchantIdManager::new(&merchants);
     let price_calculator = PriceCalculator {
-        profit: 0.3f64,
+        profit: dec!(0.3),
     };
     let amount_calculator = AmountCalculator {
-        min_amount_threshold: 1f64,
-        fee: 0.01f64
+        min_amount_threshold: dec!(1),
+        fee: dec!(0.01)
     };
     let mut limit_master = LimitMaster::new(
         Coins::TonUsdt,
@@ -195,3 +287,427 @@ fn update_and_check() {
     assert!(trades.is_ok());
     assert_eq!(trades.clone().unwrap().len(), 6, "{:#?}", trades);
 }
+
+#[test]
+fn resume_from_snapshot() {
+    let mut test_context = LimitMasterTestContext::default();
+    test_context.append(
+        "first",
+        Vec::new(),
+        Arc::new(SnifferTest::default()),
+        Arc::new(AccountantTest::default()));
+    let merchants = test_context.merchants();
+    let snapshot = OrdersSnapshot {
+        coins: Coins::TonUsdt.into(),
+        sell_stock: vec![OrderSnapshot {
+            merchant_id: "first".to_owned(),
+            id: 1337.to_string(),
+            side: Side::Buy.into(),
+            price: dec!(1.00),
+            amount: dec!(100),
+            level: 0,
+        }],
+        buy_stock: vec![OrderSnapshot {
+            merchant_id: "unknown".to_owned(),
+            id: 1338.to_string(),
+            side: Side::Sell.into(),
+            price: dec!(1.00),
+            amount: dec!(100),
+            level: 0,
+        }],
+    };
+    let path = std::env::temp_dir().join("open_midas_resume_from_snapshot.json");
+    let _ = std::fs::remove_file(&path);
+    let mut saver = LimitMasterSaver::load(&path).expect("Failed to create saver");
+    assert_eq!(saver.read_orders(Coins::TonUsdt).expect("Failed to read orders"), None);
+    let limit_master = LimitMaster::resume(
+        Coins::TonUsdt,
+        MerchantIdManager::new(&merchants),
+        PriceCalculator { profit: dec!(0.3) },
+        AmountCalculator { min_amount_threshold: dec!(1), fee: dec!(0.01) },
+        &snapshot,
+    );
+    saver.save_orders(&limit_master).expect("Failed to save orders");
+    // The snapshot is written to a temporary file which replaces the saved one.
+    assert!(!path.with_extension("json.tmp").exists());
+    let snapshot = saver.read_orders(Coins::TonUsdt)
+        .expect("Failed to read orders")
+        .expect("Snapshot is empty");
+    assert_eq!(snapshot.sell_stock.len(), 1);
+    assert_eq!(snapshot.buy_stock.len(), 0);
+
+    let mut limit_master = LimitMaster::resume(
+        Coins::TonUsdt,
+        MerchantIdManager::new(&merchants),
+        PriceCalculator { profit: dec!(0.3) },
+        AmountCalculator { min_amount_threshold: dec!(1), fee: dec!(0.01) },
+        &snapshot,
+    );
+    let trades = tokio_test::block_on(limit_master.check_current_orders());
+    assert_eq!(trades.clone().unwrap().len(), 1, "{:#?}", trades);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn reconcile_keeps_orders_within_tolerance() {
+    let trading_pair = default_buy_trading_pair();
+    let mut test_context = LimitMasterTestContext::default();
+    test_context.append(
+        "first",
+        vec![
+            create_limit_trade(trading_pair.clone(), 1337),
+            create_limit_trade(trading_pair.clone().reversed_side(), 1338),
+        ],
+        Arc::new(SnifferBuilder::new()
+            .buy_stock_generator(StockGenerator::new(Side::Buy, 0.5, 0.1, 10))
+            .sell_stock_generator(StockGenerator::new(Side::Sell, 1.0, 0.1, 10))
+            .build(100f64)),
+        Arc::new(AccountantTest::default()));
+    let merchants = test_context.merchants();
+    let mut limit_master = LimitMaster::new(
+        Coins::TonUsdt,
+        MerchantIdManager::new(&merchants),
+        PriceCalculator { profit: dec!(0.3) },
+        AmountCalculator { min_amount_threshold: dec!(1), fee: dec!(0.01) },
+    ).with_tolerance(Tolerance { price: dec!(0.01), amount: dec!(1) });
+
+    let update = tokio_test::block_on(limit_master.update_orders());
+    let update = update.expect("Failed to reconcile orders");
+    assert!(!update.changes.is_empty());
+    assert!(update.changes.iter().all(|change| match change {
+        OrderChange::Created(_) => true,
+        _ => false,
+    }), "{:#?}", update);
+    let created = test_context.traders[0].create_order_log.lock().unwrap().len();
+    assert_eq!(created, update.changes.len());
+
+    let update = tokio_test::block_on(limit_master.update_orders());
+    let update = update.expect("Failed to reconcile orders");
+    assert!(update.changes.iter().all(|change| match change {
+        OrderChange::Kept(_) => true,
+        _ => false,
+    }), "{:#?}", update);
+    assert_eq!(test_context.traders[0].create_order_log.lock().unwrap().len(), created);
+}
+
+#[test]
+fn rounded_orders_are_kept_without_tolerance() {
+    let trading_pair = default_buy_trading_pair();
+    let mut test_context = LimitMasterTestContext::default();
+    let trades = (0..2)
+        .map(|index| create_limit_trade(trading_pair.clone(), 1337 + index))
+        .chain((0..2).map(|index| {
+            create_limit_trade(trading_pair.clone().reversed_side(), 1339 + index)
+        }))
+        .collect();
+    // The balances are far above the book, so the amounts do not depend on them.
+    let exchange = Exchange::new(Coins::TonUsdt, dec!(1000000), dec!(1000000));
+    test_context.append(
+        "first",
+        trades,
+        Arc::new(SnifferBuilder::new()
+            .buy_stock_generator(StockGenerator::new(Side::Buy, 0.5, 0.1, 10))
+            .sell_stock_generator(StockGenerator::new(Side::Sell, 1.0, 0.1, 10))
+            .build(100f64)),
+        Arc::new(SharedExchange::new(exchange)));
+    let merchants = test_context.merchants();
+    let instruments = Instruments::default().with_spec(
+        "first",
+        Coins::TonUsdt,
+        InstrumentSpec {
+            tick_size: dec!(0.001),
+            lot_step: dec!(0.1),
+            ..InstrumentSpec::default()
+        });
+    let mut limit_master = LimitMaster::new(
+        Coins::TonUsdt,
+        MerchantIdManager::new(&merchants),
+        PriceCalculator { profit: dec!(0.3) },
+        AmountCalculator { min_amount_threshold: dec!(1), fee: dec!(0.01) },
+    )
+    .with_fee_model(FeeModel::new(Fees { maker: dec!(0.001), taker: dec!(0.002) }))
+    .with_instruments(instruments)
+    .with_ladder(Ladder::geometric(2, dec!(0.01), dec!(2), dec!(2)));
+
+    let update = tokio_test::block_on(limit_master.update_orders());
+    let update = update.expect("Failed to update orders");
+    assert!(update.failures.is_empty(), "{:#?}", update.failures);
+    assert_eq!(update.changes.len(), 4, "{:#?}", update);
+    let created = test_context.traders[0].create_order_log.lock().unwrap().len();
+    assert_eq!(created, 4);
+
+    let update = tokio_test::block_on(limit_master.update_orders());
+    let update = update.expect("Failed to update orders");
+    assert_eq!(update.changes.len(), 4, "{:#?}", update);
+    assert!(update.changes.iter().all(|change| match change {
+        OrderChange::Kept(_) => true,
+        _ => false,
+    }), "{:#?}", update);
+    assert_eq!(test_context.traders[0].create_order_log.lock().unwrap().len(), created);
+}
+
+#[test]
+fn failing_merchant_is_reported() {
+    let trading_pair = default_buy_trading_pair();
+    let mut test_context = LimitMasterTestContext::default();
+    test_context.append(
+        "first",
+        vec![
+            create_limit_trade(trading_pair.clone(), 1337),
+            create_limit_trade(trading_pair.clone().reversed_side(), 1338),
+        ],
+        Arc::new(SnifferBuilder::new()
+            .buy_stock_generator(StockGenerator::new(Side::Buy, 0.5, 0.1, 10))
+            .sell_stock_generator(StockGenerator::new(Side::Sell, 1.0, 0.1, 10))
+            .build(100f64)),
+        Arc::new(AccountantTest::default()));
+    test_context.merchants.push(Arc::new(MerchantTest::custom(
+        "second",
+        Arc::new(AccountantTest::default()),
+        Arc::new(NanSniffer),
+        Arc::new(FailingTrader))));
+    let merchants = test_context.merchants();
+    let mut limit_master = LimitMaster::new(
+        Coins::TonUsdt,
+        MerchantIdManager::new(&merchants),
+        PriceCalculator { profit: dec!(0.3) },
+        AmountCalculator { min_amount_threshold: dec!(1), fee: dec!(0.01) },
+    );
+
+    let update = tokio_test::block_on(limit_master.update_orders());
+    let update = update.expect("Failed to update orders");
+    assert_eq!(update.buy.len(), 1, "{:#?}", update);
+    assert_eq!(update.sell.len(), 1, "{:#?}", update);
+    assert!(update
+        .buy
+        .iter()
+        .chain(update.sell.iter())
+        .all(|entity| entity.merchant_id == "first"));
+    assert_eq!(test_context.traders[0].create_order_log.lock().unwrap().len(), 2);
+    assert!(!update.failures.is_empty());
+    let is_second = |failure: &Error| failure.merchant_id() == Some("second");
+    assert!(update.failures.iter().all(is_second), "{:#?}", update.failures);
+}
+
+fn reconcile_with_delay(delay: Duration, fan_out: FanOut) -> (Update, Duration) {
+    let trading_pair = default_buy_trading_pair();
+    let mut test_context = LimitMasterTestContext::default();
+    for (id, order_id) in [("first", 1337), ("second", 1339)].iter() {
+        let sniffer = SnifferBuilder::new()
+            .buy_stock_generator(StockGenerator::new(Side::Buy, 0.5, 0.1, 10))
+            .sell_stock_generator(StockGenerator::new(Side::Sell, 1.0, 0.1, 10))
+            .build(100f64);
+        test_context.append(
+            *id,
+            vec![
+                create_limit_trade(trading_pair.clone(), *order_id),
+                create_limit_trade(trading_pair.clone().reversed_side(), order_id + 1),
+            ],
+            Arc::new(DelayedSniffer {
+                sniffer: Arc::new(sniffer),
+                delay,
+            }),
+            Arc::new(AccountantTest::default()));
+    }
+    let merchants = test_context.merchants();
+    let mut limit_master = LimitMaster::new(
+        Coins::TonUsdt,
+        MerchantIdManager::new(&merchants),
+        PriceCalculator { profit: dec!(0.3) },
+        AmountCalculator { min_amount_threshold: dec!(1), fee: dec!(0.01) },
+    ).with_fan_out(fan_out);
+    let start = Instant::now();
+    let update = tokio_test::block_on(limit_master.update_orders());
+    (update.expect("Failed to reconcile orders"), start.elapsed())
+}
+
+fn placed_orders(update: &Update) -> Vec<(&'static str, String, f64, f64)> {
+    update.buy.iter()
+        .chain(update.sell.iter())
+        .map(|entity| (
+            entity.merchant_id,
+            entity.order.id.clone(),
+            entity.order.price,
+            entity.order.amount))
+        .collect()
+}
+
+#[test]
+fn concurrent_accumulation() {
+    let delay = Duration::from_millis(200);
+    let (expected, _elapsed) = reconcile_with_delay(Duration::from_millis(0), FanOut::default());
+    let (update, elapsed) = reconcile_with_delay(delay, FanOut::default());
+    assert!(update.failures.is_empty(), "{:#?}", update.failures);
+    assert!(!placed_orders(&update).is_empty());
+    assert_eq!(placed_orders(&update), placed_orders(&expected));
+    // Two merchants on two sides take four delays one after another.
+    assert!(elapsed < delay * 3, "{:?}", elapsed);
+
+    let fan_out = FanOut {
+        timeout: Duration::from_millis(50),
+        concurrency: 16,
+    };
+    let (update, _elapsed) = reconcile_with_delay(delay, fan_out);
+    assert_eq!(update.failures.len(), 4, "{:#?}", update.failures);
+    assert!(placed_orders(&update).is_empty());
+}
+
+#[test]
+fn ladder_of_orders() {
+    let mut test_context = LimitMasterTestContext::default();
+    let trades = (0..3)
+        .map(|index| create_limit_trade(default_buy_trading_pair(), 1337 + index))
+        .chain((0..3).map(|index| {
+            create_limit_trade(default_buy_trading_pair().reversed_side(), 1340 + index)
+        }))
+        .collect();
+    test_context.append(
+        "first",
+        trades,
+        Arc::new(SnifferBuilder::new()
+            .buy_stock_generator(StockGenerator::new(Side::Buy, 0.5, 0.1, 10))
+            .sell_stock_generator(StockGenerator::new(Side::Sell, 1.0, 0.1, 10))
+            .build(100f64)),
+        Arc::new(AccountantTest::default()));
+    let merchants = test_context.merchants();
+    let mut limit_master = LimitMaster::new(
+        Coins::TonUsdt,
+        MerchantIdManager::new(&merchants),
+        PriceCalculator { profit: dec!(0.3) },
+        AmountCalculator { min_amount_threshold: dec!(0.01), fee: dec!(0.01) },
+    ).with_ladder(Ladder::geometric(3, dec!(0.01), dec!(2), dec!(2)));
+
+    let update = tokio_test::block_on(limit_master.update_orders());
+    let update = update.expect("Failed to update orders");
+    assert!(update.failures.is_empty(), "{:#?}", update.failures);
+    for rungs in [&update.buy, &update.sell].iter() {
+        let levels: Vec<_> = rungs.iter().map(|entity| entity.level).collect();
+        assert_eq!(levels, vec![0, 1, 2], "{:#?}", update);
+        assert!(rungs[0].order.amount < rungs[1].order.amount);
+        assert!(rungs[1].order.amount < rungs[2].order.amount);
+    }
+    // Deeper rungs are further from the market.
+    assert!(update.buy[0].order.price > update.buy[2].order.price);
+    assert!(update.sell[0].order.price < update.sell[2].order.price);
+    let snapshot = limit_master.snapshot();
+    assert_eq!(snapshot.sell_stock.iter().map(|order| order.level).max(), Some(2));
+
+    // None of the rungs is on the exchange anymore, so every one of them is filled.
+    let trades = tokio_test::block_on(limit_master.check_current_orders());
+    let trades = trades.expect("Failed to check orders");
+    assert_eq!(trades.len(), 6, "{:#?}", trades);
+    let mut levels: Vec<_> = trades.iter().map(|entity| entity.level).collect();
+    levels.sort();
+    assert_eq!(levels, vec![0, 0, 1, 1, 2, 2]);
+}
+
+#[test]
+fn position_limit_stops_quoting() {
+    let trading_pair = default_buy_trading_pair();
+    let mut test_context = LimitMasterTestContext::default();
+    test_context.append(
+        "first",
+        vec![create_limit_trade(trading_pair.reversed_side(), 1337)],
+        Arc::new(SnifferBuilder::new()
+            .buy_stock_generator(StockGenerator::new(Side::Buy, 0.5, 0.1, 10))
+            .sell_stock_generator(StockGenerator::new(Side::Sell, 1.0, 0.1, 10))
+            .build(100f64)),
+        Arc::new(AccountantTest::default()));
+    let merchants = test_context.merchants();
+    // Any inventory is at the long limit, so only the sell side is quoted.
+    let inventory_skew = InventorySkew::new(dec!(-1), dec!(0.001), dec!(0.05))
+        .with_limits(dec!(-2), dec!(0));
+    let mut limit_master = LimitMaster::new(
+        Coins::TonUsdt,
+        MerchantIdManager::new(&merchants),
+        PriceCalculator { profit: dec!(0.3) },
+        AmountCalculator { min_amount_threshold: dec!(1), fee: dec!(0.01) },
+    ).with_inventory_skew(inventory_skew);
+
+    let update = tokio_test::block_on(limit_master.update_orders());
+    let update = update.expect("Failed to reconcile orders");
+    assert!(update.failures.is_empty(), "{:#?}", update.failures);
+    assert!(update.buy.is_empty(), "{:#?}", update);
+    assert_eq!(update.sell.len(), 1, "{:#?}", update);
+    assert_eq!(test_context.traders[0].create_order_log.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn volatility_spread_tightens_calm_quotes() {
+    let trading_pair = default_buy_trading_pair();
+    let mut test_context = LimitMasterTestContext::default();
+    test_context.append(
+        "first",
+        vec![
+            create_limit_trade(trading_pair.clone(), 1337),
+            create_limit_trade(trading_pair.clone().reversed_side(), 1338),
+            create_limit_trade(trading_pair.clone(), 1339),
+            create_limit_trade(trading_pair.clone().reversed_side(), 1340),
+        ],
+        Arc::new(SnifferBuilder::new()
+            .buy_stock_generator(StockGenerator::new(Side::Buy, 0.5, 0.1, 10))
+            .sell_stock_generator(StockGenerator::new(Side::Sell, 1.0, 0.1, 10))
+            .build(100f64)),
+        Arc::new(AccountantTest::default()));
+    let merchants = test_context.merchants();
+    let mut limit_master = LimitMaster::new(
+        Coins::TonUsdt,
+        MerchantIdManager::new(&merchants),
+        PriceCalculator { profit: dec!(0.3) },
+        AmountCalculator { min_amount_threshold: dec!(1), fee: dec!(0.01) },
+    ).with_spread_model(Box::new(VolatilitySpread::new(
+        10,
+        dec!(2),
+        dec!(0.05),
+        dec!(0.3),
+    )));
+
+    // A single observation keeps the quotes at the max profit.
+    let first = tokio_test::block_on(limit_master.update_orders());
+    let first = first.expect("Failed to reconcile orders");
+    // The book has not moved, so the quotes are tightened to the min profit.
+    let second = tokio_test::block_on(limit_master.update_orders());
+    let second = second.expect("Failed to reconcile orders");
+    assert_eq!(first.buy.len(), 1, "{:#?}", first);
+    assert_eq!(second.buy.len(), 1, "{:#?}", second);
+    assert!(first.buy[0].order.price < second.buy[0].order.price);
+    assert!(first.sell[0].order.price > second.sell[0].order.price);
+}
+
+#[test]
+fn risk_manager_rejects_large_orders() {
+    let trading_pair = default_buy_trading_pair();
+    let mut test_context = LimitMasterTestContext::default();
+    test_context.append(
+        "first",
+        vec![
+            create_limit_trade(trading_pair.clone(), 1337),
+            create_limit_trade(trading_pair.clone().reversed_side(), 1338),
+        ],
+        Arc::new(SnifferBuilder::new()
+            .buy_stock_generator(StockGenerator::new(Side::Buy, 0.5, 0.1, 10))
+            .sell_stock_generator(StockGenerator::new(Side::Sell, 1.0, 0.1, 10))
+            .build(100f64)),
+        Arc::new(AccountantTest::default()));
+    let merchants = test_context.merchants();
+    let risk_manager = RiskManager::new(RiskLimits {
+        max_notional: Some(dec!(0.01)),
+        ..RiskLimits::default()
+    });
+    let mut limit_master = LimitMaster::new(
+        Coins::TonUsdt,
+        MerchantIdManager::new(&merchants),
+        PriceCalculator { profit: dec!(0.3) },
+        AmountCalculator { min_amount_threshold: dec!(1), fee: dec!(0.01) },
+    ).with_risk_manager(risk_manager);
+
+    let update = tokio_test::block_on(limit_master.update_orders());
+    let update = update.expect("Failed to reconcile orders");
+    assert!(update.buy.is_empty() && update.sell.is_empty(), "{:#?}", update);
+    assert!(!update.failures.is_empty());
+    assert!(update.failures.iter().all(|failure| match failure {
+        Error::Risk { .. } => true,
+        _ => false,
+    }), "{:#?}", update.failures);
+    assert!(test_context.traders[0].create_order_log.lock().unwrap().is_empty());
+}
@@ -0,0 +1,87 @@
+use agnostic::merchant::Merchant;
+use agnostic::order::Order;
+use agnostic::trade::Trade;
+use agnostic::trading_pair::{Coins, Side, Target, TradingPair};
+use agnostic_test::accountant::Accountant as AccountantTest;
+use agnostic_test::merchant::Merchant as MerchantTest;
+use agnostic_test::sniffer::{SnifferBuilder, StockGenerator};
+use agnostic_test::trader::{TradesLogger, Trader as TraderTest};
+use open_midas::backtest::Exchange;
+use open_midas::bookkeeper::{self, Bookkeeper, Strategy};
+use open_midas::calculators::price_calculator::PriceCalculator;
+use open_midas::calculators::AmountCalculator;
+use open_midas::limit_master::{LimitMaster, MerchantIdManager};
+use open_midas::paper::PaperMerchant;
+use rust_decimal_macros::dec;
+use std::sync::Arc;
+
+#[test]
+fn paper_trading_never_reaches_the_trader() {
+    let trader = Arc::new(TradesLogger::with_orders(TraderTest::default(), Vec::new()));
+    let real = MerchantTest::custom(
+        "real",
+        Arc::new(AccountantTest::default()),
+        Arc::new(SnifferBuilder::new()
+            .buy_stock_generator(StockGenerator::new(Side::Buy, 0.5, 0.1, 10))
+            .sell_stock_generator(StockGenerator::new(Side::Sell, 1.0, 0.1, 10))
+            .build(100f64)),
+        trader.clone());
+    let path = std::env::temp_dir().join("open_midas_paper_trading.agnostic");
+    let _ = std::fs::remove_file(&path);
+    let ledger = Bookkeeper::open(path.clone()).expect("Failed to open ledger");
+    let exchange = Exchange::new(Coins::TonUsdt, dec!(100), dec!(100));
+    let paper = PaperMerchant::new(&real, exchange)
+        .with_ledger(ledger, Strategy::LimitMaster);
+    assert_eq!(paper.id(), "real");
+
+    let merchants: Vec<&dyn Merchant> = vec![&paper];
+    let mut limit_master = LimitMaster::new(
+        Coins::TonUsdt,
+        MerchantIdManager::new(&merchants),
+        PriceCalculator { profit: dec!(0.3) },
+        AmountCalculator { min_amount_threshold: dec!(1), fee: dec!(0.01) },
+    );
+    let update = tokio_test::block_on(limit_master.update_orders())
+        .expect("Failed to reconcile orders");
+    assert!(update.failures.is_empty(), "{:#?}", update);
+    let placed = update.buy.len() + update.sell.len();
+    assert!(placed > 0);
+    assert_eq!(paper.exchange().orders().len(), placed);
+    let my_orders = tokio_test::block_on(paper.sniffer().get_my_orders(TradingPair {
+        coins: Coins::TonUsdt,
+        side: Side::Buy,
+        target: Target::Limit,
+    }))
+    .expect("Failed to get my orders");
+    assert_eq!(my_orders.len(), placed);
+    tokio_test::block_on(limit_master.delete_all_my_orders())
+        .expect("Failed to delete paper orders");
+    assert!(paper.exchange().orders().is_empty());
+
+    let market_buy = Order {
+        trading_pair: TradingPair {
+            coins: Coins::TonUsdt,
+            side: Side::Buy,
+            target: Target::Market,
+        },
+        price: 0.0,
+        amount: 1.0,
+    };
+    let trade = tokio_test::block_on(paper.trader().create_order(market_buy))
+        .expect("Failed to buy on paper");
+    assert!(matches!(trade, Trade::Market(_)));
+    assert!(trader.create_order_log.lock().unwrap().is_empty());
+
+    let trades = paper
+        .ledger()
+        .expect("No paper ledger")
+        .get_all_trades()
+        .expect("Failed to read the paper ledger");
+    assert_eq!(trades.len(), 1);
+    assert_eq!(trades[0].target, bookkeeper::Target::Market);
+    assert_eq!(trades[0].amount, dec!(1));
+    assert_eq!(trades[0].merchant_id.as_deref(), Some("real"));
+    assert_eq!(trades[0].strategy, Some(Strategy::LimitMaster));
+    assert_eq!(paper.balances().0, dec!(101));
+    let _ = std::fs::remove_file(&path);
+}
@@ -1,12 +1,18 @@
+use agnostic::market::{Future, Sniffer, Trader};
 use agnostic::merchant;
-use agnostic::order::OrderWithId;
-use agnostic::trade::Trade;
+use agnostic::order::{Order, OrderWithId};
+use agnostic::trade::{Trade, TradeResult};
 use agnostic::trading_pair::{Coins, Side, Target, TradingPair};
+use agnostic_test::accountant::Accountant as AccountantTest;
 use agnostic_test::merchant::Merchant;
 use agnostic_test::sniffer::{SnifferBuilder, StockGenerator};
-use open_midas::calculators::AmountCalculator;
+use open_midas::calculators::amount_calculator::Balance;
+use open_midas::calculators::{AmountCalculator, ProfitCalculator};
+use open_midas::decimal;
 use open_midas::filters::LowAmountFilter;
-use open_midas::reseller::{Reseller, Storage};
+use open_midas::reseller::{Book, Entry, ExecutionPlan, Reseller, Storage};
+use open_midas::reseller_saver::ResellerSaver;
+use rust_decimal_macros::dec;
 use std::sync::Arc;
 use tokio_test::block_on;
 
@@ -17,10 +23,10 @@ fn default_reseller<'a>(merchants: Vec<&'a dyn merchant::Merchant>) -> Reseller<
         merchants,
         LowAmountFilter { low_amount: 0.1 },
         AmountCalculator {
-            min_amount_threshold: 0.1,
-            fee: 0.01,
+            min_amount_threshold: dec!(0.1),
+            fee: dec!(0.01),
         },
-        0.01,
+        dec!(0.01),
         false,
     )
 }
@@ -31,7 +37,7 @@ fn reseller_no_data_iteration() {
     let merchants: Vec<&dyn merchant::Merchant> = vec![&merchant];
     let mut reseller = default_reseller(merchants);
     let result = block_on(reseller.iterate());
-    assert_eq!(result, Ok(None))
+    assert_eq!(result, Ok(Vec::new()))
 }
 
 #[test]
@@ -62,12 +68,323 @@ fn reseller_with_good_orders() {
     let _result = block_on(reseller.iterate());
     let result = block_on(reseller.iterate());
     assert!(result.is_ok());
-    let result = result.unwrap();
-    assert!(result.is_some());
-    let result = result.unwrap();
+    let mut result = result.unwrap();
+    assert_eq!(result.len(), 1);
+    let result = result.remove(0);
     println!("{:#?}", reseller.buy_storage);
     println!("{:#?}", reseller.sell_storage);
-    assert_eq!(result.price(), 0.5);
+    assert_eq!(result.merchant_id, "Test");
+    assert_eq!(result.order.price(), 0.5);
     let result = block_on(reseller.iterate());
-    assert_eq!(result, Ok(None));
+    assert_eq!(result, Ok(Vec::new()));
+}
+
+/// Fills half of every market order.
+struct HalfFillTrader;
+
+impl Trader for HalfFillTrader {
+    fn create_order(&self, order: Order) -> Future<Result<Trade, String>> {
+        Box::pin(futures::future::ready(Ok(Trade::Market(TradeResult {
+            id: "1337".to_owned(),
+            trading_pair: order.trading_pair,
+            price: order.price,
+            amount: order.amount / 2.0,
+        }))))
+    }
+
+    fn delete_order(&self, _id: &str) -> Future<Result<(), String>> {
+        Box::pin(futures::future::ready(Ok(())))
+    }
+}
+
+#[test]
+fn reseller_keeps_the_unfilled_amount() {
+    let merchant = Merchant::custom(
+        "Test",
+        Arc::new(AccountantTest::default()),
+        Arc::new(
+            SnifferBuilder::new()
+                .buy_stock_generator(StockGenerator::new(Side::Buy, 0.5, 0.1, 10))
+                .sell_stock_generator(StockGenerator::new(Side::Sell, 1.0, 0.1, 10))
+                .build(100f64),
+        ),
+        Arc::new(HalfFillTrader),
+    );
+    let merchants: Vec<&dyn merchant::Merchant> = vec![&merchant];
+    let mut reseller = default_reseller(merchants);
+    reseller.accept_trade(Trade::Limit(OrderWithId {
+        id: "1337".into(),
+        trading_pair: TradingPair {
+            side: Side::Buy,
+            target: Target::Limit,
+            coins: Coins::TonUsdt,
+        },
+        price: 0.49,
+        amount: 10f64,
+    }));
+    let mut trades = Vec::new();
+    for _ in 0..2 {
+        trades.extend(block_on(reseller.iterate()).expect("Failed to resell"));
+    }
+    assert!(!trades.is_empty());
+    let filled: decimal::Decimal = trades
+        .iter()
+        .map(|trade| decimal::from_f64(trade.order.amount()))
+        .sum();
+    let left: decimal::Decimal = reseller
+        .buy_storage
+        .get(&Coins::TonUsdt)
+        .map(|entries| entries.iter().map(|entry| entry.amount).sum())
+        .unwrap_or_default();
+    assert!(left > dec!(0), "{:#?}", reseller.buy_storage);
+    assert_eq!(left + filled, dec!(10));
+}
+
+struct FailingSniffer;
+
+impl Sniffer for FailingSniffer {
+    fn all_the_best_orders(
+        &self,
+        _trading_pair: TradingPair,
+        _count: u32,
+    ) -> Future<Result<Vec<Order>, String>> {
+        Box::pin(futures::future::ready(Err("Exchange is down".to_owned())))
+    }
+
+    fn get_my_orders(
+        &self,
+        _trading_pair: TradingPair,
+    ) -> Future<Result<Vec<OrderWithId>, String>> {
+        Box::pin(futures::future::ready(Err("Exchange is down".to_owned())))
+    }
+}
+
+#[test]
+fn reseller_routes_around_a_failing_merchant() {
+    let broken = Merchant::with_sniffer("Broken", Arc::new(FailingSniffer));
+    let merchant = Merchant::with_sniffer(
+        "Test",
+        Arc::new(
+            SnifferBuilder::new()
+                .buy_stock_generator(StockGenerator::new(Side::Buy, 0.5, 0.1, 10))
+                .sell_stock_generator(StockGenerator::new(Side::Sell, 1.0, 0.1, 10))
+                .build(100f64),
+        ),
+    );
+    let merchants: Vec<&dyn merchant::Merchant> = vec![&broken, &merchant];
+    let mut reseller = default_reseller(merchants);
+    reseller.accept_trade(Trade::Limit(OrderWithId {
+        id: "1337".into(),
+        trading_pair: TradingPair {
+            side: Side::Buy,
+            target: Target::Limit,
+            coins: Coins::TonUsdt,
+        },
+        price: 0.49,
+        amount: 10f64,
+    }));
+    let mut trades = Vec::new();
+    for _ in 0..2 {
+        trades.extend(block_on(reseller.iterate()).expect("Failed to resell"));
+    }
+    assert!(!trades.is_empty());
+    assert!(trades.iter().all(|trade| trade.merchant_id == "Test"), "{:#?}", trades);
+}
+
+#[test]
+fn reseller_saver_keeps_unknown_coins() {
+    let path = std::env::temp_dir().join("open_midas_reseller_saver_keeps_unknown_coins.json");
+    std::fs::write(
+        &path,
+        r#"[{"TonUsdt":[{"price":0.49,"amount":10.0}],"BTC/USDT":[{"price":1.0,"amount":1.0}]},{}]"#,
+    ).expect("Failed to write storages");
+    let mut saver = ResellerSaver::load(&path).expect("Failed to load saver");
+    let (buy_storage, sell_storage) = saver
+        .read_buy_and_sell_storages()
+        .expect("Failed to read storages");
+    assert_eq!(buy_storage.get(&Coins::TonUsdt).map(Vec::len), Some(1));
+    assert!(sell_storage.is_empty());
+    let merchant = Merchant::default();
+    let merchants: Vec<&dyn merchant::Merchant> = vec![&merchant];
+    let mut reseller = default_reseller(merchants);
+    reseller.buy_storage = buy_storage;
+    saver.save_storages(&reseller).expect("Failed to save storages");
+    let saved = std::fs::read_to_string(&path).expect("Failed to read storages");
+    assert!(saved.contains("TON/USDT"), "{}", saved);
+    assert!(saved.contains("BTC/USDT"), "{}", saved);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn reseller_saver_backups() {
+    let path = std::env::temp_dir().join("open_midas_reseller_saver_backups.json");
+    let mut saver = ResellerSaver::load(&path)
+        .expect("Failed to load saver")
+        .with_backups(2);
+    for index in 1..=3 {
+        let _ = std::fs::remove_file(saver.backup_path(index));
+    }
+    std::fs::write(&path, "").expect("Failed to create storages");
+    let (buy_storage, sell_storage) = saver
+        .read_buy_and_sell_storages()
+        .expect("Failed to read empty storages");
+    assert!(buy_storage.is_empty() && sell_storage.is_empty());
+
+    let merchant = Merchant::default();
+    let merchants: Vec<&dyn merchant::Merchant> = vec![&merchant];
+    let mut reseller = default_reseller(merchants);
+    for price in [0.49, 0.48, 0.47].iter() {
+        reseller.accept_trade(Trade::Limit(OrderWithId {
+            id: "1337".into(),
+            trading_pair: TradingPair {
+                side: Side::Buy,
+                target: Target::Limit,
+                coins: Coins::TonUsdt,
+            },
+            price: *price,
+            amount: 10f64,
+        }));
+        saver.save_storages(&reseller).expect("Failed to save storages");
+    }
+    let saved = std::fs::read_to_string(&path).expect("Failed to read storages");
+    assert!(saved.contains("\"version\":2"), "{}", saved);
+    let (buy_storage, _sell_storage) = saver
+        .read_buy_and_sell_storages()
+        .expect("Failed to read storages");
+    assert_eq!(buy_storage.get(&Coins::TonUsdt).map(Vec::len), Some(3));
+    assert!(saver.backup_path(1).exists());
+    assert!(saver.backup_path(2).exists());
+    assert!(!saver.backup_path(3).exists());
+
+    let mut backup = ResellerSaver::load(saver.backup_path(2)).expect("Failed to load backup");
+    let (buy_storage, _sell_storage) = backup
+        .read_buy_and_sell_storages()
+        .expect("Failed to read backup");
+    assert_eq!(buy_storage.get(&Coins::TonUsdt).map(Vec::len), Some(1));
+    let _ = std::fs::remove_file(&path);
+    for index in 1..=3 {
+        let _ = std::fs::remove_file(saver.backup_path(index));
+    }
+}
+
+#[test]
+fn execution_plan_walks_profitable_levels() {
+    let pair = TradingPair {
+        coins: Coins::TonUsdt,
+        side: Side::Sell,
+        target: Target::Market,
+    };
+    let orders: Vec<Order> = [0.5, 0.49, 0.48, 0.47, 0.46, 0.45, 0.44, 0.43, 0.42, 0.41]
+        .iter()
+        .map(|price| Order {
+            trading_pair: pair.clone(),
+            price: *price,
+            amount: 5.0,
+        })
+        .collect();
+    let entry = Entry {
+        price: dec!(0.45),
+        amount: dec!(100),
+    };
+    let balance = Balance {
+        amount: dec!(1000),
+        fee: dec!(0),
+    };
+    let book = || Book {
+        orders: &orders,
+        balance: &balance,
+        profit_calculator: ProfitCalculator::default(),
+    };
+    let plan = ExecutionPlan::walk(&entry, book(), dec!(0.05)).expect("No plan");
+    assert_eq!(plan.levels.len(), 6);
+    assert_eq!(plan.amount, dec!(30));
+    assert_eq!(plan.average_price, dec!(0.475));
+    assert_eq!(plan.worst_price, dec!(0.45));
+    assert_eq!(plan.order().amount, 30.0);
+
+    let entry = Entry {
+        price: dec!(0.45),
+        amount: dec!(12),
+    };
+    let plan = ExecutionPlan::walk(&entry, book(), dec!(0.05)).expect("No plan");
+    assert_eq!(plan.levels.len(), 3);
+    assert_eq!(plan.levels[2].amount, 2.0);
+
+    let entry = Entry {
+        price: dec!(0.49),
+        amount: dec!(100),
+    };
+    assert_eq!(ExecutionPlan::walk(&entry, book(), dec!(0.05)), None);
+
+    let entry = Entry {
+        price: dec!(0.45),
+        amount: dec!(100),
+    };
+    let with_fees = Book {
+        profit_calculator: ProfitCalculator::new(dec!(0.01), dec!(0.01)),
+        ..book()
+    };
+    let plan = ExecutionPlan::walk(&entry, with_fees, dec!(0.05)).expect("No plan");
+    assert_eq!(plan.levels.len(), 4);
+}
+
+#[test]
+fn execution_plan_routes_over_several_books() {
+    let pair = TradingPair {
+        coins: Coins::TonUsdt,
+        side: Side::Sell,
+        target: Target::Market,
+    };
+    let levels = |prices: &[f64]| -> Vec<Order> {
+        prices
+            .iter()
+            .map(|price| Order {
+                trading_pair: pair.clone(),
+                price: *price,
+                amount: 5.0,
+            })
+            .collect()
+    };
+    let first = levels(&[0.5, 0.48, 0.46]);
+    let second = levels(&[0.49, 0.47, 0.45]);
+    let balance = Balance {
+        amount: dec!(1000),
+        fee: dec!(0),
+    };
+    let entry = Entry {
+        price: dec!(0.4),
+        amount: dec!(20),
+    };
+    fn book<'b>(orders: &'b [Order], balance: &'b Balance) -> Book<'b> {
+        Book {
+            orders,
+            balance,
+            profit_calculator: ProfitCalculator::default(),
+        }
+    }
+    let plans = ExecutionPlan::route(
+        &entry,
+        &[book(&first, &balance), book(&second, &balance)],
+        dec!(0.05),
+    );
+    assert_eq!(plans.len(), 2);
+    let first_plan = plans[0].as_ref().expect("No plan on the first book");
+    let second_plan = plans[1].as_ref().expect("No plan on the second book");
+    assert_eq!(first_plan.levels.len(), 2);
+    assert_eq!(second_plan.levels.len(), 2);
+    assert_eq!(first_plan.amount + second_plan.amount, dec!(20));
+    assert_eq!(first_plan.average_price, dec!(0.49));
+    assert_eq!(second_plan.worst_price, dec!(0.47));
+
+    let empty = Balance {
+        amount: dec!(0),
+        fee: dec!(0),
+    };
+    let plans = ExecutionPlan::route(
+        &entry,
+        &[book(&first, &empty), book(&second, &balance)],
+        dec!(0.05),
+    );
+    assert_eq!(plans[0], None);
+    assert_eq!(plans[1].as_ref().map(|plan| plan.levels.len()), Some(3));
 }